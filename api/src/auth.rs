@@ -0,0 +1,270 @@
+use crate::error::ApiError;
+use crate::utils;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Request};
+use axum::http::header;
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// What an API key may do, each scope includes the ones before it.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Search,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::Search => "search",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// An API key as kept in the key store, only the sha256 of the key itself is stored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    /// User whose personal commands the key manages and searches.
+    pub user: Option<String>,
+    pub created_at: u64,
+}
+
+impl ApiKey {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|granted| *granted >= scope)
+    }
+}
+
+/// How often a running server looks for changes to the key store file.
+static RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Loaded {
+    modified: Option<SystemTime>,
+    keys: Vec<ApiKey>,
+}
+
+/// API keys in a JSON file. A running server reloads it when it changes, see `watch`, so keys
+/// minted or revoked with `spellbook-server keys` apply without a restart.
+pub struct KeyStore {
+    path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+impl KeyStore {
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let store = Self {
+            path,
+            loaded: RwLock::new(Loaded {
+                modified: None,
+                keys: Vec::new(),
+            }),
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+
+    fn reload(&self) -> Result<()> {
+        let modified = self.modified();
+        let keys = match fs::read(&self.path) {
+            Ok(body) => serde_json::from_slice(&body)
+                .map_err(|e| anyhow!("Invalid key store {}: {}", self.path.display(), e))?,
+            Err(_) => Vec::new(),
+        };
+
+        let mut loaded = self.loaded.write().unwrap();
+        *loaded = Loaded { modified, keys };
+        Ok(())
+    }
+
+    fn reload_if_changed(&self) {
+        if self.modified() != self.loaded.read().unwrap().modified {
+            if let Err(e) = self.reload() {
+                tracing::error!("Keeping previously loaded API keys: {}", e);
+            }
+        }
+    }
+
+    /// Checks the file every `RELOAD_INTERVAL`, off the async workers since it touches the disk.
+    /// Requests only ever read the keys already loaded.
+    pub async fn watch(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            let store = self.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || store.reload_if_changed()).await {
+                tracing::error!("Failed to check the API key store: {}", e);
+            }
+        }
+    }
+
+    pub fn keys(&self) -> Vec<ApiKey> {
+        self.loaded.read().unwrap().keys.clone()
+    }
+
+    fn save(&self, keys: &[ApiKey]) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(keys)?)?;
+        self.reload()
+    }
+
+    pub fn authenticate(&self, key: &str) -> Option<ApiKey> {
+        let hash = utils::sha256_hex(key);
+        self.keys().into_iter().find(|stored| stored.hash == hash)
+    }
+
+    /// Creates a key, returning it with the only copy of the secret.
+    pub fn mint(
+        &self,
+        name: &str,
+        scopes: Vec<Scope>,
+        user: Option<String>,
+    ) -> Result<(ApiKey, String)> {
+        let secret = format!("sb_{}", Uuid::new_v4().simple());
+        let hash = utils::sha256_hex(&secret);
+        let key = ApiKey {
+            id: hash[..12].to_string(),
+            name: name.to_string(),
+            hash,
+            scopes,
+            user,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+
+        let mut keys = self.keys();
+        keys.push(key.clone());
+        self.save(&keys)?;
+        Ok((key, secret))
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<ApiKey> {
+        let mut keys = self.keys();
+        let position = keys
+            .iter()
+            .position(|key| key.id == id)
+            .ok_or(anyhow!("No API key with id {}", id))?;
+        let revoked = keys.remove(position);
+        self.save(&keys)?;
+        Ok(revoked)
+    }
+}
+
+pub fn unauthorized(message: &str) -> Response {
+    ApiError::Unauthorized(message.to_string()).into_response()
+}
+
+pub fn forbidden(message: &str) -> Response {
+    ApiError::Forbidden(message.to_string()).into_response()
+}
+
+/// The key sent as `Authorization: Bearer <key>` or `X-Api-Key: <key>`. Keys are never read from
+/// the query string, which ends up in request logs.
+fn presented_key(request: &Request) -> Option<String> {
+    let headers = request.headers();
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(|key| key.trim().to_string())
+}
+
+/// Resolves the request's API key and hands it to handlers as an `ApiKey` extension. Requests
+/// without a key pass through, a key that is not in the store is rejected.
+pub async fn authenticate(
+    Extension(keys): Extension<Arc<KeyStore>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(presented) = presented_key(&request) {
+        match keys.authenticate(&presented) {
+            Some(key) => {
+                request.extensions_mut().insert(key);
+            }
+            None => return unauthorized("Invalid API key"),
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Rejects requests whose API key lacks `scope`.
+async fn require(scope: Scope, request: Request, next: Next) -> Response {
+    match request.extensions().get::<ApiKey>() {
+        None => unauthorized(&format!(
+            "An API key with the {} scope is required",
+            scope.name()
+        )),
+        Some(key) if !key.allows(scope) => forbidden(&format!(
+            "API key {} lacks the {} scope",
+            key.id,
+            scope.name()
+        )),
+        Some(_) => next.run(request).await,
+    }
+}
+
+// Route layers for `middleware::from_fn`, one per scope.
+
+pub async fn require_search(request: Request, next: Next) -> Response {
+    require(Scope::Search, request, next).await
+}
+
+pub async fn require_write(request: Request, next: Next) -> Response {
+    require(Scope::Write, request, next).await
+}
+
+pub async fn require_admin(request: Request, next: Next) -> Response {
+    require(Scope::Admin, request, next).await
+}
+
+/// The user a request was made by, from an API key minted for them.
+pub struct User(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for User
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<ApiKey>() {
+            None => Err(unauthorized("An API key is required")),
+            Some(ApiKey { user: None, id, .. }) => Err(forbidden(&format!(
+                "API key {} does not belong to a user",
+                id
+            ))),
+            Some(ApiKey {
+                user: Some(user), ..
+            }) => Ok(User(user.clone())),
+        }
+    }
+}
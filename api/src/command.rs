@@ -1,34 +1,26 @@
+use crate::utils;
 use anyhow::Result;
-use qdrant_client::qdrant::ScoredPoint;
-use serde::{Deserialize, Serialize};
+use qdrant_client::qdrant::Value;
 use serde_json::json;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Command {
-    pub name: String,
-    pub commands: Vec<SubCommand>,
-}
+pub use spellbook_command::{Command, Placeholder, PlaceholderType, SubCommand};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Eq, Hash)]
-pub struct SubCommand {
-    pub command: String,
-    pub description: String,
-    pub placeholders: Option<Vec<Placeholder>>,
+pub trait CommandId {
+    /// Id shared by all of the command's points. Personal commands are scoped to their owner so
+    /// they never collide with the registry's or another user's copy of the same command.
+    fn id(&self, owner: Option<&str>) -> String;
 }
 
-impl ToString for SubCommand {
-    fn to_string(&self) -> String {
-        format!("{} : {}", self.command, self.description)
+impl CommandId for SubCommand {
+    fn id(&self, owner: Option<&str>) -> String {
+        match owner {
+            Some(owner) => utils::uuid_hash(&format!("{}/{}", owner, self.command)),
+            None => utils::uuid_hash(&self.command),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Eq, Hash)]
-pub struct Placeholder {
-    pub name: String,
-    pub description: String,
-}
-
 pub trait CompareSubCommands {
     fn compare(&self, other: &Self) -> (Vec<SubCommand>, Vec<SubCommand>);
 }
@@ -47,11 +39,9 @@ impl CompareSubCommands for Vec<SubCommand> {
     }
 }
 
-impl TryFrom<ScoredPoint> for SubCommand {
-    type Error = anyhow::Error;
-    fn try_from(scored_point: ScoredPoint) -> Result<Self> {
-        let payload_str = json!(scored_point.payload).to_string();
-        let sub_command = serde_json::from_str::<SubCommand>(&payload_str)?;
-        Ok(sub_command)
-    }
+/// The command stored in a scored or retrieved point's payload.
+pub fn from_payload(payload: &HashMap<String, Value>) -> Result<SubCommand> {
+    let payload_str = json!(payload).to_string();
+    let sub_command = serde_json::from_str::<SubCommand>(&payload_str)?;
+    Ok(sub_command)
 }
@@ -0,0 +1,498 @@
+use crate::open_ai::EMBEDDING_MODEL;
+use crate::poller::PollConfig;
+use crate::registry::{self, DEFAULT_BRANCH};
+use crate::vector_db::DEFAULT_COLLECTION;
+use anyhow::{anyhow, Result};
+use axum::http::{header, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Read when no `--config` or `SPELLBOOK_CONFIG` is given, if it exists.
+static DEFAULT_CONFIG_FILE: &str = "spellbook.toml";
+
+/// A setting that is never printed, `config check` shows it redacted.
+#[derive(Deserialize, Clone)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+/// Flags that take precedence over the configuration file and the environment.
+#[derive(clap::Args, Debug, Default)]
+pub struct ConfigArgs {
+    /// Configuration file, `spellbook.toml` when it exists
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Address to listen on, such as `127.0.0.1:8080`
+    #[arg(long, global = true)]
+    bind: Option<String>,
+    /// Directory of the web app's static files
+    #[arg(long, global = true)]
+    static_dir: Option<PathBuf>,
+    /// Directory registries keep their ledgers and clones in
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+}
+
+/// Settings of the server, from defaults overridden by the configuration file, then by the
+/// environment and then by command line flags.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// TOML file listing more registries, see `RegistryConfig`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registries_file: Option<PathBuf>,
+    pub server: ServerConfig,
+    pub default_registry: DefaultRegistryConfig,
+    pub embedding: EmbeddingConfig,
+    pub vector_store: VectorStoreConfig,
+    pub webhook: WebhookConfig,
+    pub rerank: RerankConfig,
+    pub fill: FillConfig,
+    pub health: HealthConfig,
+    /// Environment variables whose values could not be used, reported by `check`.
+    #[serde(skip)]
+    pub env_problems: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub static_dir: PathBuf,
+    pub data_dir: PathBuf,
+    /// JSON file of the API keys minted with `spellbook-server keys`.
+    pub api_keys_file: PathBuf,
+    /// Origins allowed to call the API from a browser, `*` for any.
+    pub cors_origins: Vec<String>,
+    /// Whether searching works without an API key.
+    pub public_search: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:8080".to_string(),
+            static_dir: PathBuf::from("dist"),
+            data_dir: PathBuf::from("data"),
+            api_keys_file: PathBuf::from("api_keys.json"),
+            cors_origins: vec!["*".to_string()],
+            public_search: true,
+        }
+    }
+}
+
+/// Where the default registry's commands come from, the other registries set the same in the
+/// registries file.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DefaultRegistryConfig {
+    /// `owner/repo` whose pushes the registry accepts, any when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    /// Branch whose pushes are indexed.
+    pub branch: String,
+    /// Local directory of registry files to index and watch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollConfig>,
+}
+
+impl Default for DefaultRegistryConfig {
+    fn default() -> Self {
+        Self {
+            repository: None,
+            branch: DEFAULT_BRANCH.to_string(),
+            directory: None,
+            poll: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingProvider {
+    #[default]
+    OpenAi,
+}
+
+/// How the default registry's commands are embedded.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddingConfig {
+    pub provider: EmbeddingProvider,
+    pub model: String,
+    /// Template rendering the embedded texts, `template::DEFAULT_TEMPLATE` when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<Secret>,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            provider: EmbeddingProvider::default(),
+            model: EMBEDDING_MODEL.to_string(),
+            template: None,
+            api_key: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorBackend {
+    #[default]
+    Qdrant,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct VectorStoreConfig {
+    pub backend: VectorBackend,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Secret>,
+    /// Collection of the default registry.
+    pub collection: String,
+}
+
+impl Default for VectorStoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: VectorBackend::default(),
+            url: None,
+            token: None,
+            collection: DEFAULT_COLLECTION.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Secret the default registry's webhooks are signed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<Secret>,
+    /// Secrets of other registries by name, used over their `webhook_secret_env`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub registries: BTreeMap<String, Secret>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RerankerKind {
+    Heuristic,
+    Llm,
+}
+
+/// How search candidates are reordered after the vector search.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RerankConfig {
+    /// Reranking is disabled when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reranker: Option<RerankerKind>,
+    /// Chat model of the `llm` reranker.
+    pub model: String,
+}
+
+impl Default for RerankConfig {
+    fn default() -> Self {
+        Self {
+            reranker: None,
+            model: "gpt-3.5-turbo".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractorKind {
+    #[default]
+    Rules,
+    Llm,
+}
+
+/// How `/fill` extracts placeholder values from a query.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FillConfig {
+    pub extractor: ExtractorKind,
+    /// Chat model of the `llm` extractor.
+    pub model: String,
+}
+
+impl Default for FillConfig {
+    fn default() -> Self {
+        Self {
+            extractor: ExtractorKind::default(),
+            model: "gpt-3.5-turbo".to_string(),
+        }
+    }
+}
+
+/// What `/health/ready` expects of the server's dependencies.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// How long a probe of the embedding provider is reused for.
+    pub embedding_probe_ttl_secs: u64,
+    /// Polling registries not checked successfully within this long are not ready, unchecked when
+    /// unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ingestion_age_secs: Option<u64>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            embedding_probe_ttl_secs: 300,
+            max_ingestion_age_secs: None,
+        }
+    }
+}
+
+/// An environment variable, unless it is unset or empty.
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+impl Config {
+    /// Resolves the configuration from every source, failing on a file that does not parse.
+    pub fn load(args: &ConfigArgs) -> Result<Self> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| var("SPELLBOOK_CONFIG").map(PathBuf::from));
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env();
+        config.apply_args(args);
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read config {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| anyhow!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Environment variables kept from before the configuration file, and a few new ones.
+    fn apply_env(&mut self) {
+        if let Some(path) = var("REGISTRIES_FILE") {
+            self.registries_file = Some(PathBuf::from(path));
+        }
+
+        let mut problems = Vec::new();
+        let mut invalid = |name: &str, value: &str, expected: &str| {
+            problems.push(format!("{} is {:?}, expected {}", name, value, expected));
+        };
+
+        let server = &mut self.server;
+        if let Some(bind) = var("BIND_ADDRESS") {
+            server.bind = bind;
+        }
+        if let Some(dir) = var("STATIC_DIR") {
+            server.static_dir = PathBuf::from(dir);
+        }
+        if let Some(dir) = var("DATA_DIR") {
+            server.data_dir = PathBuf::from(dir);
+        }
+        if let Some(path) = var("API_KEYS_FILE") {
+            server.api_keys_file = PathBuf::from(path);
+        }
+        if let Some(origins) = var("CORS_ORIGINS") {
+            server.cors_origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        if let Some(public_search) = var("PUBLIC_SEARCH") {
+            match parse_bool(&public_search) {
+                Some(public_search) => server.public_search = public_search,
+                None => invalid("PUBLIC_SEARCH", &public_search, "true or false"),
+            }
+        }
+
+        let embedding = &mut self.embedding;
+        if let Some(model) = var("EMBEDDING_MODEL") {
+            embedding.model = model;
+        }
+        if let Some(template) = var("EMBEDDING_TEMPLATE") {
+            embedding.template = Some(template);
+        }
+        if let Some(key) = var("OPENAI_TOKEN") {
+            embedding.api_key = Some(Secret(key));
+        }
+
+        let vector_store = &mut self.vector_store;
+        if let Some(url) = var("QDRANT_URL") {
+            vector_store.url = Some(url);
+        }
+        if let Some(token) = var("QDRANT_TOKEN") {
+            vector_store.token = Some(Secret(token));
+        }
+        if let Some(collection) = var("VECTOR_COLLECTION") {
+            vector_store.collection = collection;
+        }
+
+        if let Some(secret) = var("WEBHOOK_SECRET") {
+            self.webhook.secret = Some(Secret(secret));
+        }
+
+        let default_registry = &mut self.default_registry;
+        if let Some(repository) = var("REGISTRY_REPOSITORY") {
+            default_registry.repository = Some(repository);
+        }
+        if let Some(branch) = var("REGISTRY_BRANCH") {
+            default_registry.branch = branch;
+        }
+        if let Some(dir) = var("REGISTRY_DIRECTORY") {
+            default_registry.directory = Some(PathBuf::from(dir));
+        }
+        if let Some(remote) = var("REGISTRY_POLL_REMOTE") {
+            match &mut default_registry.poll {
+                Some(poll) => poll.remote = remote,
+                None => default_registry.poll = Some(PollConfig::new(remote)),
+            }
+        }
+        if let Some(poll) = &mut default_registry.poll {
+            if let Some(branch) = var("REGISTRY_POLL_BRANCH") {
+                poll.branch = branch;
+            }
+            if let Some(interval) = var("REGISTRY_POLL_INTERVAL") {
+                match interval.trim().parse() {
+                    Ok(interval) => poll.interval_secs = interval,
+                    Err(_) => invalid("REGISTRY_POLL_INTERVAL", &interval, "a number of seconds"),
+                }
+            }
+        }
+
+        match var("RERANKER").as_deref() {
+            Some("heuristic") => self.rerank.reranker = Some(RerankerKind::Heuristic),
+            Some("llm") => self.rerank.reranker = Some(RerankerKind::Llm),
+            Some(other) => invalid("RERANKER", other, "heuristic or llm"),
+            None => {}
+        }
+        if let Some(model) = var("RERANK_MODEL") {
+            self.rerank.model = model;
+        }
+        match var("PLACEHOLDER_EXTRACTOR").as_deref() {
+            Some("llm") => self.fill.extractor = ExtractorKind::Llm,
+            Some("rules") => self.fill.extractor = ExtractorKind::Rules,
+            Some(other) => invalid("PLACEHOLDER_EXTRACTOR", other, "llm or rules"),
+            None => {}
+        }
+        if let Some(model) = var("PLACEHOLDER_MODEL") {
+            self.fill.model = model;
+        }
+
+        self.env_problems = problems;
+    }
+
+    fn apply_args(&mut self, args: &ConfigArgs) {
+        if let Some(bind) = &args.bind {
+            self.server.bind = bind.clone();
+        }
+        if let Some(dir) = &args.static_dir {
+            self.server.static_dir = dir.clone();
+        }
+        if let Some(dir) = &args.data_dir {
+            self.server.data_dir = dir.clone();
+        }
+    }
+
+    /// Fails with every problem found, such as each required setting that is missing, rather
+    /// than only the first.
+    pub fn check(&self) -> Result<()> {
+        let mut problems = self.env_problems.clone();
+        if self.vector_store.url.is_none() {
+            problems.push("vector_store.url (QDRANT_URL) is not set".to_string());
+        }
+        if self.vector_store.token.is_none() {
+            problems.push("vector_store.token (QDRANT_TOKEN) is not set".to_string());
+        }
+        if self.embedding.api_key.is_none() {
+            problems.push("embedding.api_key (OPENAI_TOKEN) is not set".to_string());
+        }
+        if let Err(e) = self.cors() {
+            problems.push(e.to_string());
+        }
+        problems.extend(registry::check_registries_file(self));
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        Err(anyhow!(
+            "Invalid configuration:\n  {}",
+            problems.join("\n  ")
+        ))
+    }
+
+    /// Lets the configured origins call the API from a browser, sending an API key.
+    pub fn cors(&self) -> Result<CorsLayer> {
+        let origins = &self.server.cors_origins;
+        let cors = CorsLayer::new().allow_methods(Any).allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-api-key"),
+        ]);
+        if origins.iter().any(|origin| origin == "*") {
+            return Ok(cors.allow_origin(Any));
+        }
+
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .map_err(|_| anyhow!("server.cors_origins has an invalid origin {}", origin))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(cors.allow_origin(AllowOrigin::list(origins)))
+    }
+
+    /// The configuration as TOML, with secrets redacted.
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
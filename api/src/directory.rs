@@ -0,0 +1,296 @@
+use crate::command::Command;
+use crate::indexer::{self, FileChanges, Files, IngestSummary};
+use crate::registry::Registry;
+use anyhow::{anyhow, Result};
+use notify::{RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+static SOURCE: &str = "directory";
+
+/// Editors write files in several steps, changes are applied once events stop for this long.
+static SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+fn is_registry_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if is_registry_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads every registry file under `dir` by its path relative to `dir`. A file that does not
+/// parse, such as one saved halfway through an edit, keeps its previous contents so its commands
+/// stay indexed until it is fixed. Without previous contents, as on startup, such a file fails the
+/// read, since syncing without it would remove the commands it had indexed.
+fn read_files(dir: &Path, previous: Option<&Files>) -> Result<Files> {
+    let mut paths = Vec::new();
+    collect(dir, &mut paths)?;
+
+    let mut files = Files::new();
+    for path in paths {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let relative = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+        if let Err(e) = serde_json::from_str::<Command>(&content) {
+            let Some(previous) = previous else {
+                return Err(anyhow!("Invalid registry file {}: {}", path.display(), e));
+            };
+            tracing::warn!("Ignoring invalid registry file {}: {}", path.display(), e);
+            if let Some(previous) = previous.get(&relative) {
+                files.insert(relative, previous.clone());
+            }
+            continue;
+        }
+        files.insert(relative, content);
+    }
+    Ok(files)
+}
+
+fn diff(previous: &Files, current: &Files) -> FileChanges {
+    let mut changes = FileChanges::default();
+    for (path, content) in current {
+        match previous.get(path) {
+            None => {
+                changes.added.insert(path.clone(), content.clone());
+            }
+            Some(old) if old != content => {
+                changes
+                    .modified
+                    .insert(path.clone(), (content.clone(), old.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    for (path, content) in previous {
+        if !current.contains_key(path) {
+            changes.removed.insert(path.clone(), content.clone());
+        }
+    }
+    changes
+}
+
+async fn apply(registry: &Registry, changes: &FileChanges) -> Result<IngestSummary> {
+    let transaction = registry.ledger.begin().await;
+    let summary = indexer::apply_changes(
+        &registry.vector_client,
+        &registry.template,
+        indexer::origin::DIRECTORY,
+        changes,
+    )
+    .await?;
+    transaction.commit_changes(SOURCE, None, changes, summary)?;
+    Ok(summary)
+}
+
+/// Brings the registry's index in line with every file in the directory, returning the files
+/// indexed unless it failed.
+async fn sync(registry: &Registry, dir: &Path) -> Option<Files> {
+    match sync_files(registry, dir).await {
+        Ok(files) => Some(files),
+        Err(e) => {
+            tracing::error!("Failed to sync {}: {:#}", dir.display(), e);
+            None
+        }
+    }
+}
+
+async fn sync_files(registry: &Registry, dir: &Path) -> Result<Files> {
+    let files = read_files(dir, None)?;
+
+    let transaction = registry.ledger.begin().await;
+    let summary = indexer::sync(
+        &registry.vector_client,
+        &registry.template,
+        indexer::origin::DIRECTORY,
+        &files,
+    )
+    .await?;
+    transaction.commit_sync(SOURCE, None, &files, summary)?;
+    tracing::info!(
+        "Synced {} with {}: {} commands added, {} commands removed",
+        registry.name,
+        dir.display(),
+        summary.added,
+        summary.removed
+    );
+    Ok(files)
+}
+
+/// Brings the registry's index in line with the directory, then keeps it there by re-reading the
+/// directory whenever something in it changes and applying only what changed. Until a sync
+/// succeeds every change syncs the whole directory again.
+pub async fn watch(registry: Arc<Registry>, dir: PathBuf) -> Result<()> {
+    let mut indexed = sync(&registry, &dir).await;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|event| event.paths.iter().any(|path| is_registry_file(path))) {
+            let _ = sender.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::Recursive)?;
+
+    while receiver.recv().await.is_some() {
+        tokio::time::sleep(SETTLE_DELAY).await;
+        while receiver.try_recv().is_ok() {}
+
+        let Some(files) = &indexed else {
+            indexed = sync(&registry, &dir).await;
+            continue;
+        };
+        let current = match read_files(&dir, Some(files)) {
+            Ok(current) => current,
+            Err(e) => {
+                tracing::error!("Failed to read {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        let changes = diff(files, &current);
+        if changes.is_empty() {
+            continue;
+        }
+
+        match apply(&registry, &changes).await {
+            Ok(summary) => {
+                indexed = Some(current);
+                tracing::info!(
+                    "Applied changes in {} to {}: {} commands added, {} commands removed",
+                    dir.display(),
+                    registry.name,
+                    summary.added,
+                    summary.removed
+                );
+            }
+            Err(e) => tracing::error!("Failed to apply changes in {}: {:#}", dir.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(entries: &[(&str, &str)]) -> Files {
+        entries
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_sorts_files_into_added_removed_and_modified() {
+        let previous = files(&[
+            ("git.json", r#"{"commands": []}"#),
+            ("tar.json", r#"{"commands": []}"#),
+            ("kubectl/get.json", r#"{"commands": []}"#),
+        ]);
+        let current = files(&[
+            ("git.json", r#"{"commands": []}"#),
+            (
+                "kubectl/get.json",
+                r#"{"commands": [{"command": "kubectl get pods"}]}"#,
+            ),
+            ("docker.json", r#"{"commands": []}"#),
+        ]);
+
+        let changes = diff(&previous, &current);
+
+        assert_eq!(
+            changes.added,
+            files(&[("docker.json", r#"{"commands": []}"#)])
+        );
+        assert_eq!(
+            changes.removed,
+            files(&[("tar.json", r#"{"commands": []}"#)])
+        );
+        assert_eq!(
+            changes.modified.get("kubectl/get.json"),
+            Some(&(
+                r#"{"commands": [{"command": "kubectl get pods"}]}"#.to_string(),
+                r#"{"commands": []}"#.to_string()
+            ))
+        );
+        assert_eq!(changes.modified.len(), 1);
+    }
+
+    #[test]
+    fn diff_of_identical_files_is_empty() {
+        let previous = files(&[("git.json", r#"{"commands": []}"#)]);
+
+        assert!(diff(&previous, &previous.clone()).is_empty());
+        assert!(diff(&Files::new(), &Files::new()).is_empty());
+    }
+
+    #[test]
+    fn invalid_files_keep_their_previous_contents() {
+        let dir = std::env::temp_dir().join(format!("spellbook-directory-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("nested/tar.json"), r#"{"commands": ["#).unwrap();
+        fs::write(dir.join("notes.txt"), "not a registry file").unwrap();
+        fs::write(
+            dir.join(".hidden.json"),
+            r#"{"name": "hidden", "commands": []}"#,
+        )
+        .unwrap();
+        let valid = r#"{"name": "git", "commands": [{"command": "git status", "description": "Show the working tree status"}]}"#;
+        fs::write(dir.join("git.json"), valid).unwrap();
+
+        let previous = files(&[("nested/tar.json", r#"{"commands": []}"#)]);
+        let read = read_files(&dir, Some(&previous));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            read.unwrap(),
+            files(&[
+                ("git.json", valid),
+                ("nested/tar.json", r#"{"commands": []}"#)
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_files_fail_the_startup_read() {
+        let dir = std::env::temp_dir().join(format!(
+            "spellbook-directory-startup-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let valid = r#"{"name": "git", "commands": [{"command": "git status", "description": "Show the working tree status"}]}"#;
+        fs::write(dir.join("git.json"), valid).unwrap();
+        fs::write(dir.join("tar.json"), r#"{"name": "tar", "commands": ["#).unwrap();
+
+        let startup = read_files(&dir, None);
+        let watching = read_files(&dir, Some(&Files::new()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let error = startup.unwrap_err().to_string();
+        assert!(error.contains("tar.json"), "{}", error);
+        assert_eq!(watching.unwrap(), files(&[("git.json", valid)]));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::placeholder::PlaceholderError;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::response::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::fmt;
+use std::panic;
+use uuid::Uuid;
+
+/// Marks which upstream service an error came from, attached as `anyhow` context where the
+/// service is called so handlers can report it without knowing where it happened.
+#[derive(Debug, Clone, Copy)]
+pub enum Upstream {
+    Embedding,
+    Completion,
+    VectorStore,
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Upstream::Embedding => write!(f, "Embedding request failed"),
+            Upstream::Completion => write!(f, "Completion request failed"),
+            Upstream::VectorStore => write!(f, "Vector store request failed"),
+        }
+    }
+}
+
+/// Everything a handler can fail with. Upstream and internal errors keep their cause for the
+/// server log, clients only see a generic explanation.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    InvalidCommand(Vec<PlaceholderError>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Upstream(Upstream, anyhow::Error),
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    /// An error caused by what the client sent, unless an upstream service failed on the way.
+    pub fn bad_input(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<Upstream>() {
+            Some(_) => error.into(),
+            None => ApiError::BadRequest(error.to_string()),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidCommand(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Upstream(Upstream::VectorStore, _) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Upstream(_, _) => StatusCode::BAD_GATEWAY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier clients can match on, unlike the title and detail.
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidCommand(_) => "invalid_command",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Upstream(Upstream::Embedding, _) => "embedding_failed",
+            ApiError::Upstream(Upstream::Completion, _) => "completion_failed",
+            ApiError::Upstream(Upstream::VectorStore, _) => "vector_store_unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    fn problem(self) -> Problem {
+        let status = self.status();
+        let code = self.code();
+        let (detail, errors, cause) = match self {
+            ApiError::BadRequest(detail)
+            | ApiError::Unauthorized(detail)
+            | ApiError::Forbidden(detail)
+            | ApiError::NotFound(detail)
+            | ApiError::Conflict(detail) => (detail, None, None),
+            ApiError::InvalidCommand(errors) => (
+                "The command has invalid placeholders".to_string(),
+                Some(errors),
+                None,
+            ),
+            ApiError::Upstream(upstream, error) => {
+                (upstream.to_string(), None, Some(format!("{:#}", error)))
+            }
+            ApiError::Internal(error) => (
+                "Something went wrong".to_string(),
+                None,
+                Some(format!("{:#}", error)),
+            ),
+        };
+
+        Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            code: code.to_string(),
+            detail,
+            errors,
+            request_id: None,
+            cause,
+        }
+    }
+}
+
+impl<E> From<E> for ApiError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        let err = err.into();
+        match err.downcast_ref::<Upstream>() {
+            Some(&upstream) => ApiError::Upstream(upstream, err),
+            None => ApiError::Internal(err),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let problem = self.problem();
+        let mut response = problem.to_response();
+        // Picked up by `request_id`, which adds the id and logs the cause.
+        response.extensions_mut().insert(problem);
+        response
+    }
+}
+
+/// An RFC 9457 problem document.
+#[derive(Serialize, Debug, Clone)]
+struct Problem {
+    /// Errors are told apart by `code`, so no documentation is linked.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: String,
+    status: u16,
+    code: String,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<PlaceholderError>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// What actually went wrong, only ever logged.
+    #[serde(skip)]
+    cause: Option<String>,
+}
+
+impl Problem {
+    fn to_response(&self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = (status, Json(self)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
+
+static REQUEST_ID: &str = "x-request-id";
+
+/// Gives every request an id, echoed in the `X-Request-Id` response header and in problem
+/// documents so a client's report can be matched with the server log. Errors are logged here with
+/// their cause, and plain text rejections from extractors become problem documents too.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map_or_else(|| Uuid::new_v4().to_string(), String::from);
+
+    let response = next.run(request).await;
+    let (mut parts, body) = response.into_parts();
+
+    let problem = match parts.extensions.remove::<Problem>() {
+        Some(problem) => Some(problem),
+        None => rejection_problem(&parts),
+    };
+
+    let mut response = match problem {
+        Some(mut problem) => {
+            match &problem.cause {
+                Some(cause) => tracing::error!(request_id = %id, code = %problem.code, "{}", cause),
+                None => {
+                    tracing::debug!(request_id = %id, code = %problem.code, "{}", problem.detail)
+                }
+            }
+            if problem.detail.is_empty() {
+                problem.detail = rejection_detail(body).await;
+            }
+            problem.request_id = Some(id.clone());
+
+            // Keep headers set on the way out, such as CORS or `WWW-Authenticate`.
+            let mut response = problem.to_response();
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.remove(header::CONTENT_TYPE);
+            response.headers_mut().extend(parts.headers);
+            response
+        }
+        None => Response::from_parts(parts, body),
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    response
+}
+
+/// Extractor rejections, such as malformed JSON or a missing query parameter, are plain text
+/// client errors. Their text becomes the problem's detail.
+fn rejection_problem(parts: &Parts) -> Option<Problem> {
+    let is_text = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/plain"));
+    if !parts.status.is_client_error() || !is_text {
+        return None;
+    }
+
+    let mut problem = match parts.status {
+        StatusCode::NOT_FOUND => ApiError::NotFound(String::new()),
+        StatusCode::UNAUTHORIZED => ApiError::Unauthorized(String::new()),
+        StatusCode::FORBIDDEN => ApiError::Forbidden(String::new()),
+        StatusCode::CONFLICT => ApiError::Conflict(String::new()),
+        _ => ApiError::BadRequest(String::new()),
+    }
+    .problem();
+    problem.status = parts.status.as_u16();
+    problem.title = parts
+        .status
+        .canonical_reason()
+        .unwrap_or_default()
+        .to_string();
+    Some(problem)
+}
+
+async fn rejection_detail(body: Body) -> String {
+    match axum::body::to_bytes(body, 64 * 1024).await {
+        Ok(body) => String::from_utf8_lossy(&body).to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Logs every panic with a backtrace, since the panic handler below only sees the message.
+pub fn log_panics() {
+    panic::set_hook(Box::new(|info| {
+        tracing::error!("{}\n{}", info, Backtrace::force_capture());
+    }));
+}
+
+/// Turns a panic in a handler into a 500 problem document instead of a dropped connection.
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    };
+    ApiError::Internal(anyhow::anyhow!("Handler panicked: {}", message)).into_response()
+}
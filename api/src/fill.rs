@@ -0,0 +1,400 @@
+use crate::command::{Placeholder, PlaceholderType, SubCommand};
+use crate::config::{ExtractorKind, FillConfig};
+use crate::open_ai;
+use crate::placeholder::{self, PlaceholderError};
+use crate::search::SearchResult;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+/// The top search result with its placeholders filled in from the query.
+#[derive(Serialize, Debug)]
+pub struct FilledCommand {
+    pub command: String,
+    pub template: String,
+    pub values: HashMap<String, String>,
+    pub missing: Vec<String>,
+    pub errors: Vec<PlaceholderError>,
+    pub result: SearchResult,
+}
+
+impl FilledCommand {
+    pub fn new(result: SearchResult, values: HashMap<String, String>) -> Self {
+        let (values, errors) = placeholder::resolve(&result.command, &values);
+        let missing = result
+            .command
+            .placeholder_names()
+            .into_iter()
+            .filter(|name| !values.contains_key(name))
+            .collect();
+
+        FilledCommand {
+            command: result.command.substitute(&values).trim().to_string(),
+            template: result.command.command.clone(),
+            values,
+            missing,
+            errors,
+            result,
+        }
+    }
+}
+
+/// Extracts values for a command's placeholders from a natural language query.
+#[async_trait]
+pub trait PlaceholderExtractor: Send + Sync {
+    async fn extract(&self, query: &str, command: &SubCommand) -> Result<HashMap<String, String>>;
+}
+
+/// The configured extractor, `llm` asks a chat model and `rules` uses the local rules.
+pub fn configured(config: &FillConfig) -> Arc<dyn PlaceholderExtractor> {
+    match config.extractor {
+        ExtractorKind::Llm => Arc::new(LlmExtractor {
+            model: config.model.clone(),
+        }),
+        ExtractorKind::Rules => Arc::new(RuleExtractor),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueKind {
+    Url,
+    Ip,
+    Size,
+    Number,
+    Path,
+    Text,
+}
+
+impl ValueKind {
+    fn regex(&self) -> &'static Regex {
+        static REGEXES: OnceLock<Vec<Regex>> = OnceLock::new();
+        let regexes = REGEXES.get_or_init(|| {
+            [
+                r"https?://\S+",
+                r"\b\d{1,3}(?:\.\d{1,3}){3}\b",
+                r"(?i)\b\d+(?:\.\d+)?\s?[kmgt]i?b?\b",
+                r"\b\d+\b",
+                r"(?:~|\.{1,2})?/\S*|\b[\w.-]+\.[A-Za-z0-9]{1,5}\b",
+                r#""[^"]*"|'[^']*'"#,
+            ]
+            .iter()
+            .map(|pattern| Regex::new(pattern).unwrap())
+            .collect()
+        });
+
+        &regexes[*self as usize]
+    }
+
+    /// Guesses what kind of value a placeholder takes from its type, or failing that its name
+    /// and description.
+    fn of(name: &str, placeholder: Option<&Placeholder>) -> Option<Self> {
+        match placeholder.and_then(|placeholder| placeholder.kind) {
+            Some(PlaceholderType::Integer) | Some(PlaceholderType::Number) => {
+                return Some(ValueKind::Number)
+            }
+            Some(PlaceholderType::Path) => return Some(ValueKind::Path),
+            _ => {}
+        }
+
+        let description = placeholder.map_or("", |placeholder| placeholder.description.as_str());
+        let words = format!("{} {}", name, description).to_lowercase();
+        let mentions = |keywords: &[&str]| {
+            words
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| keywords.contains(&word))
+        };
+
+        if mentions(&["url", "link", "endpoint"]) {
+            Some(ValueKind::Url)
+        } else if mentions(&["ip", "host", "address"]) {
+            Some(ValueKind::Ip)
+        } else if mentions(&["size", "bytes"]) {
+            Some(ValueKind::Size)
+        } else if mentions(&[
+            "port", "pid", "count", "number", "num", "n", "lines", "depth",
+        ]) {
+            Some(ValueKind::Number)
+        } else if mentions(&["path", "file", "dir", "directory", "folder", "filename"]) {
+            Some(ValueKind::Path)
+        } else if mentions(&["message", "msg", "text", "pattern", "query", "string"]) {
+            Some(ValueKind::Text)
+        } else {
+            None
+        }
+    }
+
+    fn normalize(&self, value: &str) -> String {
+        match self {
+            // `100MB` becomes `100M` and `100KB` becomes `100k`, the units `find -size` and
+            // most cli tools accept.
+            ValueKind::Size => {
+                let size = value.replace(' ', "");
+                let size = size
+                    .trim_end_matches(['b', 'B'])
+                    .trim_end_matches(['i', 'I']);
+                let (number, unit) = size.split_at(size.len() - 1);
+                if unit.eq_ignore_ascii_case("k") {
+                    format!("{}k", number)
+                } else {
+                    format!("{}{}", number, unit.to_uppercase())
+                }
+            }
+            ValueKind::Text => value[1..value.len() - 1].to_string(),
+            _ => value.to_string(),
+        }
+    }
+}
+
+/// Local extraction, first taking one of a placeholder's choices or the word following its name
+/// in the query (`branch main`), then the first unused value that looks like what it expects.
+pub struct RuleExtractor;
+
+#[async_trait]
+impl PlaceholderExtractor for RuleExtractor {
+    async fn extract(&self, query: &str, command: &SubCommand) -> Result<HashMap<String, String>> {
+        let declared: HashMap<&str, &Placeholder> = command
+            .placeholders
+            .iter()
+            .flatten()
+            .map(|placeholder| (placeholder.name.as_str(), placeholder))
+            .collect();
+        let words: Vec<&str> = query.split_whitespace().collect();
+        let mut used: Vec<String> = Vec::new();
+        let mut values = HashMap::new();
+
+        for name in command.placeholder_names() {
+            let placeholder = declared.get(name.as_str()).copied();
+            let chosen = placeholder
+                .and_then(|placeholder| placeholder.choices.as_ref())
+                .and_then(|choices| {
+                    choices
+                        .iter()
+                        .find(|choice| words.iter().any(|word| choice.eq_ignore_ascii_case(word)))
+                })
+                .map(String::as_str);
+            let named = words
+                .windows(2)
+                .find(|pair| pair[0].eq_ignore_ascii_case(&name))
+                .map(|pair| pair[1]);
+
+            let guessed = ValueKind::of(&name, placeholder).and_then(|kind| {
+                kind.regex()
+                    .find_iter(query)
+                    // Only whole words, so a path is not picked out of the middle of a url.
+                    .filter(|found| !query[..found.start()].ends_with(|c: char| !c.is_whitespace()))
+                    .map(|found| found.as_str())
+                    .find(|found| !used.iter().any(|value| value == found))
+                    .map(|found| (found.to_string(), kind.normalize(found)))
+            });
+
+            let (raw, value) = match (chosen.or(named), guessed) {
+                (Some(word), _) if !used.iter().any(|value| value == word) => {
+                    (word.to_string(), word.to_string())
+                }
+                (_, Some(guessed)) => guessed,
+                _ => continue,
+            };
+
+            used.push(raw);
+            values.insert(name, value);
+        }
+
+        Ok(values)
+    }
+}
+
+/// Asks a chat model to pick placeholder values out of the query.
+pub struct LlmExtractor {
+    model: String,
+}
+
+static LLM_EXTRACT_PROMPT: &str =
+    "You fill in the placeholders of a cli command from a user's request. \
+Reply with only a JSON object mapping placeholder names to values. \
+Leave out placeholders the request does not give a value for.";
+
+#[async_trait]
+impl PlaceholderExtractor for LlmExtractor {
+    async fn extract(&self, query: &str, command: &SubCommand) -> Result<HashMap<String, String>> {
+        let names = command.placeholder_names();
+        if names.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let placeholders = command
+            .placeholders
+            .iter()
+            .flatten()
+            .map(|placeholder| format!("- {}: {}", placeholder.name, placeholder.description))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let prompt = format!(
+            "Command: {}\nPlaceholders:\n{}\n\nRequest: {}",
+            command.command, placeholders, query
+        );
+
+        let reply = open_ai::complete(&self.model, LLM_EXTRACT_PROMPT, &prompt).await?;
+        let reply: HashMap<String, Value> = serde_json::from_str(reply.trim())
+            .map_err(|e| anyhow!("Unexpected extraction reply {:?}: {}", reply, e))?;
+
+        Ok(reply
+            .into_iter()
+            .filter(|(name, _)| names.contains(name))
+            .filter_map(|(name, value)| match value {
+                Value::String(value) => Some((name, value)),
+                Value::Number(value) => Some((name, value.to_string())),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn command(command: &str, placeholders: Value) -> SubCommand {
+        serde_json::from_value(json!({
+            "command": command,
+            "description": "",
+            "placeholders": placeholders,
+        }))
+        .unwrap()
+    }
+
+    async fn extract(query: &str, command: &SubCommand) -> HashMap<String, String> {
+        RuleExtractor.extract(query, command).await.unwrap()
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn extracts_a_size_and_a_path() {
+        let find = command(
+            "find {path} -size +{size}",
+            json!([
+                {"name": "path", "description": "Directory to search", "type": "path"},
+                {"name": "size", "description": "Minimum file size"}
+            ]),
+        );
+
+        assert_eq!(
+            extract("files bigger than 100M under /var", &find).await,
+            values(&[("path", "/var"), ("size", "100M")])
+        );
+        assert_eq!(
+            extract("find files bigger than 100MB in /var/log", &find).await,
+            values(&[("path", "/var/log"), ("size", "100M")])
+        );
+        assert_eq!(
+            extract("files over 512 kb in ~/Downloads", &find).await,
+            values(&[("path", "~/Downloads"), ("size", "512k")])
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_the_word_after_the_placeholder_name() {
+        let lsof = command(
+            "lsof -i :{port}",
+            json!([{"name": "port", "description": "Port to inspect"}]),
+        );
+
+        assert_eq!(
+            extract("what is listening on port 8080", &lsof).await,
+            values(&[("port", "8080")])
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_one_of_the_choices() {
+        let kill = command(
+            "kill -s {signal} {pid}",
+            json!([
+                {"name": "signal", "description": "Signal to send", "choices": ["TERM", "KILL", "HUP"]},
+                {"name": "pid", "description": "Process id", "type": "integer"}
+            ]),
+        );
+
+        assert_eq!(
+            extract("send kill to process 4242", &kill).await,
+            values(&[("signal", "KILL"), ("pid", "4242")])
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_pick_a_path_out_of_a_url() {
+        let curl = command(
+            "curl -o {path} {url}",
+            json!([
+                {"name": "path", "description": "File to write", "type": "path"},
+                {"name": "url", "description": "Address to download"}
+            ]),
+        );
+
+        assert_eq!(
+            extract("download https://example.com/a.tar.gz to ./a.tar.gz", &curl).await,
+            values(&[
+                ("path", "./a.tar.gz"),
+                ("url", "https://example.com/a.tar.gz")
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_out_placeholders_without_a_match() {
+        let tar = command(
+            "tar -xf {archive}",
+            json!([{"name": "archive", "description": "Archive to extract"}]),
+        );
+        let find = command(
+            "find {path} -size +{size}",
+            json!([
+                {"name": "path", "description": "Directory to search", "type": "path"},
+                {"name": "size", "description": "Minimum file size"}
+            ]),
+        );
+
+        assert!(extract("extract it here", &tar).await.is_empty());
+        assert_eq!(
+            extract("large files in /tmp", &find).await,
+            values(&[("path", "/tmp")])
+        );
+    }
+
+    #[tokio::test]
+    async fn uses_each_value_once_in_order() {
+        let cp = command(
+            "cp {source_file} {target_file}",
+            json!([
+                {"name": "source_file", "description": "File to copy"},
+                {"name": "target_file", "description": "Copy to create"}
+            ]),
+        );
+        let diff = command(
+            "diff {file} {other_file}",
+            json!([
+                {"name": "file", "description": "First file"},
+                {"name": "other_file", "description": "Second file"}
+            ]),
+        );
+
+        assert_eq!(
+            extract("copy notes.txt to backup.txt", &cp).await,
+            values(&[("source_file", "notes.txt"), ("target_file", "backup.txt")])
+        );
+        assert_eq!(
+            extract("compare notes.txt with itself", &diff).await,
+            values(&[("file", "notes.txt")])
+        );
+    }
+}
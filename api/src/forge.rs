@@ -0,0 +1,920 @@
+use crate::indexer::{FileChanges, Files};
+use anyhow::{anyhow, Result};
+use axum::http::HeaderMap;
+use git2::{Delta, DiffFile, Oid, Repository, Tree};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::path::Path;
+
+/// A git host whose push webhooks spellbook understands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    /// Gitea and its fork Forgejo, which share a payload format.
+    Gitea,
+    Bitbucket,
+}
+
+impl Forge {
+    /// Tells forges apart by their event header. Gitea also sends GitHub's headers, so it is
+    /// checked first.
+    pub fn detect(headers: &HeaderMap) -> Option<Self> {
+        if headers.contains_key("x-gitea-event") || headers.contains_key("x-forgejo-event") {
+            Some(Forge::Gitea)
+        } else if headers.contains_key("x-gitlab-event") {
+            Some(Forge::GitLab)
+        } else if headers.contains_key("x-github-event") {
+            Some(Forge::GitHub)
+        } else if headers.contains_key("x-event-key") {
+            Some(Forge::Bitbucket)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "github",
+            Forge::GitLab => "gitlab",
+            Forge::Gitea => "gitea",
+            Forge::Bitbucket => "bitbucket",
+        }
+    }
+
+    fn event<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let header = match self {
+            Forge::GitHub => "x-github-event",
+            Forge::GitLab => "x-gitlab-event",
+            Forge::Gitea if headers.contains_key("x-forgejo-event") => "x-forgejo-event",
+            Forge::Gitea => "x-gitea-event",
+            Forge::Bitbucket => "x-event-key",
+        };
+        headers.get(header)?.to_str().ok()
+    }
+
+    /// Whether the delivery is a push, other events such as pings are acknowledged and ignored.
+    pub fn is_push(&self, headers: &HeaderMap) -> bool {
+        let event = self.event(headers).unwrap_or_default();
+        match self {
+            Forge::GitHub | Forge::Gitea => event == "push",
+            Forge::GitLab => event == "Push Hook",
+            Forge::Bitbucket => event == "repo:push",
+        }
+    }
+
+    /// Whether the delivery carries the forge's proof of the shared secret.
+    pub fn is_signed(&self, headers: &HeaderMap) -> bool {
+        self.signature_header(headers).is_some()
+    }
+
+    fn signature_header<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let header = match self {
+            Forge::GitHub => "x-hub-signature-256",
+            Forge::GitLab => "x-gitlab-token",
+            Forge::Gitea if headers.contains_key("x-forgejo-signature") => "x-forgejo-signature",
+            Forge::Gitea => "x-gitea-signature",
+            Forge::Bitbucket => "x-hub-signature",
+        };
+        headers.get(header)?.to_str().ok()
+    }
+
+    /// Checks the delivery against the webhook secret. GitLab sends the secret itself, the other
+    /// forges an HMAC-SHA256 of the body keyed with it, prefixed with `sha256=` except on Gitea.
+    pub fn verify(&self, secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+        let Some(value) = self.signature_header(headers) else {
+            return false;
+        };
+
+        let signature = match self {
+            Forge::GitLab => return constant_time_eq(value.as_bytes(), secret.as_bytes()),
+            Forge::Gitea => value,
+            Forge::GitHub | Forge::Bitbucket => match value.strip_prefix("sha256=") {
+                Some(signature) => signature,
+                None => return false,
+            },
+        };
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Reads a push payload into the fields spellbook needs from it. Bitbucket reports every
+    /// pushed branch in one delivery, the change to `branch` is read when there is one.
+    pub fn parse_push(&self, body: &[u8], branch: &str) -> Result<PushEvent> {
+        let event = match self {
+            Forge::GitHub => serde_json::from_slice::<GitHubPush>(body)?.into(),
+            Forge::GitLab => serde_json::from_slice::<GitLabPush>(body)?.into(),
+            Forge::Gitea => serde_json::from_slice::<GiteaPush>(body)?.into(),
+            Forge::Bitbucket => serde_json::from_slice::<BitbucketPush>(body)?.event(branch)?,
+        };
+        Ok(event)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// A push to a registry repository, whatever forge it came from.
+#[derive(Debug, Clone)]
+pub struct PushEvent {
+    pub clone_url: String,
+    /// `owner/repo`, compared against the repository a registry tracks.
+    pub full_name: String,
+    /// The pushed ref, such as `refs/heads/main`.
+    pub git_ref: String,
+    /// Commit before the push, all zeros when the push created the branch.
+    pub before: String,
+    /// Commit after the push, all zeros when the push deleted the branch.
+    pub after: String,
+    pub pusher: String,
+}
+
+impl PushEvent {
+    pub fn deletes_branch(&self) -> bool {
+        is_null_sha(&self.after)
+    }
+
+    /// The pushed branch, `None` for tags.
+    pub fn branch(&self) -> Option<&str> {
+        self.git_ref.strip_prefix("refs/heads/")
+    }
+}
+
+fn is_null_sha(sha: &str) -> bool {
+    sha.is_empty() || sha.chars().all(|c| c == '0')
+}
+
+#[derive(Deserialize, Debug)]
+struct GitHubPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    before: String,
+    after: String,
+    repository: GitHubRepository,
+    pusher: GitHubPusher,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitHubRepository {
+    clone_url: String,
+    full_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitHubPusher {
+    name: String,
+}
+
+impl From<GitHubPush> for PushEvent {
+    fn from(push: GitHubPush) -> Self {
+        Self {
+            clone_url: push.repository.clone_url,
+            full_name: push.repository.full_name,
+            git_ref: push.git_ref,
+            before: push.before,
+            after: push.after,
+            pusher: push.pusher.name,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GitLabPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    before: String,
+    after: String,
+    user_username: String,
+    project: GitLabProject,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitLabProject {
+    git_http_url: String,
+    path_with_namespace: String,
+}
+
+impl From<GitLabPush> for PushEvent {
+    fn from(push: GitLabPush) -> Self {
+        Self {
+            clone_url: push.project.git_http_url,
+            full_name: push.project.path_with_namespace,
+            git_ref: push.git_ref,
+            before: push.before,
+            after: push.after,
+            pusher: push.user_username,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    before: String,
+    after: String,
+    repository: GitHubRepository,
+    pusher: GiteaUser,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaUser {
+    login: String,
+}
+
+impl From<GiteaPush> for PushEvent {
+    fn from(push: GiteaPush) -> Self {
+        Self {
+            clone_url: push.repository.clone_url,
+            full_name: push.repository.full_name,
+            git_ref: push.git_ref,
+            before: push.before,
+            after: push.after,
+            pusher: push.pusher.login,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketPush {
+    actor: BitbucketActor,
+    repository: BitbucketRepository,
+    push: BitbucketChanges,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketActor {
+    display_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketRepository {
+    full_name: String,
+    links: BitbucketLinks,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketLinks {
+    html: BitbucketLink,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketLink {
+    href: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketChanges {
+    changes: Vec<BitbucketChange>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketChange {
+    old: Option<BitbucketRef>,
+    new: Option<BitbucketRef>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketRef {
+    /// `branch` or `tag`.
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    target: BitbucketTarget,
+}
+
+impl BitbucketRef {
+    fn git_ref(&self) -> String {
+        match self.kind.as_str() {
+            "branch" => format!("refs/heads/{}", self.name),
+            "tag" => format!("refs/tags/{}", self.name),
+            kind => format!("refs/{}/{}", kind, self.name),
+        }
+    }
+}
+
+impl BitbucketChange {
+    /// The ref the change is to, read from the old ref when the change deleted it.
+    fn git_ref(&self) -> Option<String> {
+        self.new
+            .as_ref()
+            .or(self.old.as_ref())
+            .map(BitbucketRef::git_ref)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketTarget {
+    hash: String,
+}
+
+impl BitbucketPush {
+    /// Bitbucket reports one change per pushed branch or tag, the change to `branch` is used, or
+    /// else the first one. Created and deleted branches have no old or new ref.
+    fn event(self, branch: &str) -> Result<PushEvent> {
+        let branch_ref = format!("refs/heads/{}", branch);
+        let mut changes = self.push.changes;
+        let position = changes
+            .iter()
+            .position(|change| change.git_ref().as_ref() == Some(&branch_ref))
+            .unwrap_or(0);
+        if changes.is_empty() {
+            return Err(anyhow!("Bitbucket push has no changes"));
+        }
+        let change = changes.swap_remove(position);
+        let git_ref = change
+            .git_ref()
+            .ok_or(anyhow!("Bitbucket change has neither an old nor a new ref"))?;
+        let hash = |reference: Option<BitbucketRef>| {
+            reference.map_or(String::new(), |reference| reference.target.hash)
+        };
+
+        Ok(PushEvent {
+            clone_url: self.repository.links.html.href,
+            full_name: self.repository.full_name,
+            git_ref,
+            before: hash(change.old),
+            after: hash(change.new),
+            pusher: self.actor.display_name,
+        })
+    }
+}
+
+fn blob_content(repo: &Repository, id: Oid) -> Result<String> {
+    let blob = repo.find_blob(id)?;
+    Ok(String::from_utf8(blob.content().to_vec())?)
+}
+
+/// Compares two trees of a repository, keeping only registry files. Renames count as removing the
+/// old file and adding the new one.
+pub fn diff_trees(
+    repo: &Repository,
+    prev_tree: Option<&Tree>,
+    curr_tree: Option<&Tree>,
+) -> Result<FileChanges> {
+    let mut changes = FileChanges::default();
+    let only_json =
+        |path: Option<&Path>| path.is_some_and(|path| path.extension() == Some("json".as_ref()));
+
+    let diff = repo.diff_tree_to_tree(prev_tree, curr_tree, None)?;
+    for delta in diff.deltas() {
+        let old_file = delta.old_file();
+        let new_file = delta.new_file();
+        let path = |file: &DiffFile| {
+            file.path()
+                .map_or(String::new(), |path| path.to_string_lossy().to_string())
+        };
+        let old_is_registry_file = only_json(old_file.path());
+        let new_is_registry_file = only_json(new_file.path());
+
+        match delta.status() {
+            Delta::Added | Delta::Copied if new_is_registry_file => {
+                changes
+                    .added
+                    .insert(path(&new_file), blob_content(repo, new_file.id())?);
+            }
+            Delta::Deleted if old_is_registry_file => {
+                changes
+                    .removed
+                    .insert(path(&old_file), blob_content(repo, old_file.id())?);
+            }
+            Delta::Modified | Delta::Renamed | Delta::Typechange => {
+                match (old_is_registry_file, new_is_registry_file) {
+                    (true, true) if old_file.path() == new_file.path() => {
+                        changes.modified.insert(
+                            path(&new_file),
+                            (
+                                blob_content(repo, new_file.id())?,
+                                blob_content(repo, old_file.id())?,
+                            ),
+                        );
+                    }
+                    (old, new) => {
+                        if old {
+                            changes
+                                .removed
+                                .insert(path(&old_file), blob_content(repo, old_file.id())?);
+                        }
+                        if new {
+                            changes
+                                .added
+                                .insert(path(&new_file), blob_content(repo, new_file.id())?);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(changes)
+}
+
+/// What indexing a registry commit takes, given the commit the index last reflected.
+pub enum Update {
+    /// The index already reflects the commit.
+    Unchanged,
+    /// The commit is older than the indexed one, such as a webhook delivered out of order.
+    Behind,
+    /// Files changed since the base commit.
+    Changes { base: String, changes: FileChanges },
+    /// Every file of the commit, when there is no base to diff against, such as after a force
+    /// push.
+    Full(Files),
+}
+
+/// Works out how to bring the index from `indexed` to `head`. Without an indexed commit `base`
+/// is diffed against instead, a push's `before` for example, and missing both everything is
+/// synced.
+pub fn plan(
+    repo: &Repository,
+    head: Oid,
+    indexed: Option<&str>,
+    base: Option<&str>,
+) -> Result<Update> {
+    let head_tree = repo.find_commit(head)?.tree()?;
+    let commit = |sha: &str| {
+        Oid::from_str(sha)
+            .ok()
+            .filter(|_| !is_null_sha(sha))
+            .and_then(|oid| repo.find_commit(oid).ok())
+    };
+
+    let base = match indexed.and_then(commit) {
+        Some(indexed) if indexed.id() == head => return Ok(Update::Unchanged),
+        Some(indexed) if repo.graph_descendant_of(indexed.id(), head)? => {
+            return Ok(Update::Behind)
+        }
+        Some(indexed) if repo.graph_descendant_of(head, indexed.id())? => Some(indexed),
+        // The indexed commit is not in this history, the branch was rewritten.
+        Some(_) => None,
+        None if indexed.is_some() => None,
+        None => base.and_then(commit),
+    };
+
+    match base {
+        Some(base) => Ok(Update::Changes {
+            base: base.id().to_string(),
+            changes: diff_trees(repo, Some(&base.tree()?), Some(&head_tree))?,
+        }),
+        None => Ok(Update::Full(
+            diff_trees(repo, None, Some(&head_tree))?.added,
+        )),
+    }
+}
+
+/// Clones the pushed repository into `repo_path`, replacing any earlier clone, and plans how to
+/// index the pushed commit from the `indexed` one.
+pub fn process_push(event: &PushEvent, repo_path: &Path, indexed: Option<&str>) -> Result<Update> {
+    tracing::info!(
+        "Processing push of {} to {} by {}",
+        event.after,
+        event.full_name,
+        event.pusher
+    );
+    if repo_path.exists() {
+        std::fs::remove_dir_all(repo_path)?;
+    }
+    let repo = Repository::clone(&event.clone_url, repo_path)?;
+
+    plan(
+        &repo,
+        Oid::from_str(&event.after)?,
+        indexed,
+        Some(&event.before),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    static GITHUB_PUSH: &str = r#"{
+        "ref": "refs/heads/main",
+        "before": "6113728f27ae82c7b1a177c8d03f9e96e0adf246",
+        "after": "0000000000000000000000000000000000000000",
+        "created": false,
+        "deleted": true,
+        "forced": false,
+        "compare": "https://github.com/synoet/spellbook-registry/compare/6113728f27ae...000000000000",
+        "commits": [],
+        "head_commit": null,
+        "repository": {
+            "id": 186853002,
+            "name": "spellbook-registry",
+            "full_name": "synoet/spellbook-registry",
+            "private": false,
+            "clone_url": "https://github.com/synoet/spellbook-registry.git",
+            "default_branch": "main"
+        },
+        "pusher": {"name": "synoet", "email": "synoet@users.noreply.github.com"},
+        "sender": {"login": "synoet", "id": 21707876, "type": "User"}
+    }"#;
+
+    static GITLAB_PUSH: &str = r#"{
+        "object_kind": "push",
+        "event_name": "push",
+        "before": "95790bf891e76fee5e1747ab589903a6a1f80f22",
+        "after": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+        "ref": "refs/heads/main",
+        "ref_protected": true,
+        "checkout_sha": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+        "user_id": 4,
+        "user_name": "John Smith",
+        "user_username": "jsmith",
+        "project_id": 15,
+        "project": {
+            "id": 15,
+            "name": "Diaspora",
+            "web_url": "http://example.com/mike/diaspora",
+            "git_ssh_url": "git@example.com:mike/diaspora.git",
+            "git_http_url": "http://example.com/mike/diaspora.git",
+            "namespace": "Mike",
+            "path_with_namespace": "mike/diaspora",
+            "default_branch": "main"
+        },
+        "commits": [],
+        "total_commits_count": 4
+    }"#;
+
+    static GITEA_PUSH: &str = r#"{
+        "ref": "refs/heads/develop",
+        "before": "28e1879d029cb852e4844d9c718537df08844e03",
+        "after": "bffeb74224043ba2feb48d137756c8a9331c449a",
+        "compare_url": "http://localhost:3000/gitea/webhooks/compare/28e1879d029cb852e4844d9c718537df08844e03...bffeb74224043ba2feb48d137756c8a9331c449a",
+        "commits": [],
+        "repository": {
+            "id": 140,
+            "owner": {"id": 1, "login": "gitea", "full_name": "Gitea"},
+            "name": "webhooks",
+            "full_name": "gitea/webhooks",
+            "private": false,
+            "html_url": "http://localhost:3000/gitea/webhooks",
+            "clone_url": "http://localhost:3000/gitea/webhooks.git",
+            "default_branch": "master"
+        },
+        "pusher": {"id": 1, "login": "gitea", "full_name": "Gitea"},
+        "sender": {"id": 1, "login": "gitea", "full_name": "Gitea"}
+    }"#;
+
+    static BITBUCKET_PUSH: &str = r#"{
+        "push": {
+            "changes": [
+                {
+                    "old": {
+                        "type": "branch",
+                        "name": "feature",
+                        "target": {"type": "commit", "hash": "1e65c05c1d5171631d92438a13901ca7dae9618c"}
+                    },
+                    "new": {
+                        "type": "branch",
+                        "name": "feature",
+                        "target": {"type": "commit", "hash": "24f6e7bdbd4b2a8c05a3ae14a4d7a98b5a0e8fc3"}
+                    },
+                    "created": false,
+                    "forced": false,
+                    "closed": false
+                },
+                {
+                    "old": null,
+                    "new": {
+                        "type": "branch",
+                        "name": "main",
+                        "target": {"type": "commit", "hash": "b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9"}
+                    },
+                    "created": true,
+                    "forced": false,
+                    "closed": false
+                }
+            ]
+        },
+        "repository": {
+            "type": "repository",
+            "full_name": "team/registry",
+            "name": "registry",
+            "links": {"html": {"href": "https://bitbucket.org/team/registry"}}
+        },
+        "actor": {"type": "user", "display_name": "Emma", "nickname": "emma"}
+    }"#;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn hmac_hex(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn detects_forges_by_their_event_header() {
+        let gitea = headers(&[("x-gitea-event", "push"), ("x-github-event", "push")]);
+        assert_eq!(Forge::detect(&gitea), Some(Forge::Gitea));
+        let forgejo = headers(&[("x-forgejo-event", "push")]);
+        assert_eq!(Forge::detect(&forgejo), Some(Forge::Gitea));
+        let gitlab = headers(&[("x-gitlab-event", "Push Hook")]);
+        assert_eq!(Forge::detect(&gitlab), Some(Forge::GitLab));
+        assert!(Forge::GitLab.is_push(&gitlab));
+        let bitbucket = headers(&[("x-event-key", "repo:push")]);
+        assert_eq!(Forge::detect(&bitbucket), Some(Forge::Bitbucket));
+        assert!(Forge::Bitbucket.is_push(&bitbucket));
+        let ping = headers(&[("x-github-event", "ping")]);
+        assert!(!Forge::GitHub.is_push(&ping));
+        assert_eq!(Forge::detect(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn parses_github_pushes() {
+        let event = Forge::GitHub
+            .parse_push(GITHUB_PUSH.as_bytes(), "main")
+            .unwrap();
+        assert_eq!(event.full_name, "synoet/spellbook-registry");
+        assert_eq!(
+            event.clone_url,
+            "https://github.com/synoet/spellbook-registry.git"
+        );
+        assert_eq!(event.branch(), Some("main"));
+        assert_eq!(event.pusher, "synoet");
+        assert!(event.deletes_branch());
+    }
+
+    #[test]
+    fn parses_gitlab_pushes() {
+        let event = Forge::GitLab
+            .parse_push(GITLAB_PUSH.as_bytes(), "main")
+            .unwrap();
+        assert_eq!(event.full_name, "mike/diaspora");
+        assert_eq!(event.clone_url, "http://example.com/mike/diaspora.git");
+        assert_eq!(event.branch(), Some("main"));
+        assert_eq!(event.before, "95790bf891e76fee5e1747ab589903a6a1f80f22");
+        assert_eq!(event.after, "da1560886d4f094c3e6c9ef40349f7d38b5d27d7");
+        assert_eq!(event.pusher, "jsmith");
+        assert!(!event.deletes_branch());
+    }
+
+    #[test]
+    fn parses_gitea_pushes() {
+        let event = Forge::Gitea
+            .parse_push(GITEA_PUSH.as_bytes(), "main")
+            .unwrap();
+        assert_eq!(event.full_name, "gitea/webhooks");
+        assert_eq!(event.branch(), Some("develop"));
+        assert_eq!(event.pusher, "gitea");
+    }
+
+    #[test]
+    fn reads_the_bitbucket_change_to_the_indexed_branch() {
+        let event = Forge::Bitbucket
+            .parse_push(BITBUCKET_PUSH.as_bytes(), "main")
+            .unwrap();
+        assert_eq!(event.full_name, "team/registry");
+        assert_eq!(event.clone_url, "https://bitbucket.org/team/registry");
+        assert_eq!(event.branch(), Some("main"));
+        assert_eq!(event.before, "");
+        assert_eq!(event.after, "b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9");
+        assert_eq!(event.pusher, "Emma");
+
+        let event = Forge::Bitbucket
+            .parse_push(BITBUCKET_PUSH.as_bytes(), "release")
+            .unwrap();
+        assert_eq!(event.branch(), Some("feature"));
+    }
+
+    #[test]
+    fn reads_tags_as_refs_that_are_not_branches() {
+        let body = BITBUCKET_PUSH.replace(r#""type": "branch""#, r#""type": "tag""#);
+        let event = Forge::Bitbucket
+            .parse_push(body.as_bytes(), "main")
+            .unwrap();
+        assert_eq!(event.git_ref, "refs/tags/feature");
+        assert_eq!(event.branch(), None);
+    }
+
+    #[test]
+    fn verifies_github_and_bitbucket_signatures() {
+        let body = GITHUB_PUSH.as_bytes();
+        let signature = format!("sha256={}", hmac_hex("secret", body));
+
+        for (forge, header) in [
+            (Forge::GitHub, "x-hub-signature-256"),
+            (Forge::Bitbucket, "x-hub-signature"),
+        ] {
+            let signed = headers(&[(header, &signature)]);
+            assert!(forge.is_signed(&signed));
+            assert!(forge.verify("secret", &signed, body));
+            assert!(!forge.verify("other", &signed, body));
+            assert!(!forge.verify("secret", &signed, b"tampered"));
+
+            let unprefixed = headers(&[(header, &hmac_hex("secret", body))]);
+            assert!(!forge.verify("secret", &unprefixed, body));
+        }
+    }
+
+    #[test]
+    fn verifies_gitea_and_forgejo_signatures() {
+        let body = GITEA_PUSH.as_bytes();
+        let signature = hmac_hex("secret", body);
+
+        let gitea = headers(&[("x-gitea-signature", &signature)]);
+        assert!(Forge::Gitea.verify("secret", &gitea, body));
+        assert!(!Forge::Gitea.verify("other", &gitea, body));
+
+        let forgejo = headers(&[("x-forgejo-signature", &signature)]);
+        assert!(Forge::Gitea.verify("secret", &forgejo, body));
+    }
+
+    #[test]
+    fn verifies_gitlab_tokens() {
+        let body = GITLAB_PUSH.as_bytes();
+        let token = headers(&[("x-gitlab-token", "secret")]);
+        assert!(Forge::GitLab.verify("secret", &token, body));
+        assert!(!Forge::GitLab.verify("secret2", &token, body));
+        assert!(!Forge::GitLab.verify("secret", &HeaderMap::new(), body));
+        assert!(!Forge::GitLab.is_signed(&HeaderMap::new()));
+    }
+
+    static GIT: &str = r#"{"name": "git", "commands": [{"command": "git status", "description": "Show the working tree status"}]}"#;
+    static GIT_LOG: &str = r#"{"name": "git", "commands": [{"command": "git status", "description": "Show the working tree status"}, {"command": "git log --oneline", "description": "List commits one per line"}]}"#;
+    static TAR: &str = r#"{"name": "tar", "commands": [{"command": "tar -xf {{archive}}", "description": "Extract an archive"}]}"#;
+    static DOCKER: &str = r#"{"name": "docker", "commands": [{"command": "docker ps", "description": "List running containers"}]}"#;
+
+    /// An empty repository in a fresh directory, removed again when dropped.
+    struct TestRepo {
+        dir: PathBuf,
+        repo: Repository,
+    }
+
+    impl TestRepo {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "spellbook-forge-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            let repo = Repository::init(&dir).unwrap();
+            TestRepo { dir, repo }
+        }
+
+        /// Commits exactly `files` on top of `parents`.
+        fn commit(&self, files: &[(&str, &str)], parents: &[Oid]) -> Oid {
+            let mut builder = self.repo.treebuilder(None).unwrap();
+            for (path, content) in files {
+                let blob = self.repo.blob(content.as_bytes()).unwrap();
+                builder.insert(path, blob, 0o100644).unwrap();
+            }
+            let tree = self.repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents: Vec<_> = parents
+                .iter()
+                .map(|parent| self.repo.find_commit(*parent).unwrap())
+                .collect();
+            let signature = git2::Signature::now("spellbook", "spellbook@example.com").unwrap();
+            self.repo
+                .commit(
+                    None,
+                    &signature,
+                    &signature,
+                    "Update registry",
+                    &tree,
+                    &parents.iter().collect::<Vec<_>>(),
+                )
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn files(entries: &[(&str, &str)]) -> Files {
+        entries
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn plans_nothing_for_the_indexed_commit() {
+        let test = TestRepo::new("unchanged");
+        let head = test.commit(&[("git.json", GIT)], &[]);
+
+        let update = plan(&test.repo, head, Some(&head.to_string()), None).unwrap();
+        assert!(matches!(update, Update::Unchanged));
+    }
+
+    #[test]
+    fn plans_nothing_for_a_commit_older_than_the_indexed_one() {
+        let test = TestRepo::new("behind");
+        let first = test.commit(&[("git.json", GIT)], &[]);
+        let second = test.commit(&[("git.json", GIT_LOG)], &[first]);
+
+        let update = plan(&test.repo, first, Some(&second.to_string()), None).unwrap();
+        assert!(matches!(update, Update::Behind));
+    }
+
+    #[test]
+    fn plans_the_changes_since_the_indexed_commit() {
+        let test = TestRepo::new("changes");
+        let first = test.commit(&[("git.json", GIT), ("tar.json", TAR)], &[]);
+        let second = test.commit(
+            &[
+                ("git.json", GIT_LOG),
+                ("docker.json", DOCKER),
+                ("README.md", "# Registry"),
+            ],
+            &[first],
+        );
+        let third = test.commit(
+            &[
+                ("git.json", GIT_LOG),
+                ("docker.json", DOCKER),
+                ("README.md", "# Commands"),
+            ],
+            &[second],
+        );
+
+        // Diffed from the indexed commit, even though the push claims to start at `second`.
+        let update = plan(
+            &test.repo,
+            third,
+            Some(&first.to_string()),
+            Some(&second.to_string()),
+        )
+        .unwrap();
+        let Update::Changes { base, changes } = update else {
+            panic!("expected changes");
+        };
+        assert_eq!(base, first.to_string());
+        assert_eq!(changes.added, files(&[("docker.json", DOCKER)]));
+        assert_eq!(changes.removed, files(&[("tar.json", TAR)]));
+        assert_eq!(
+            changes.modified.get("git.json"),
+            Some(&(GIT_LOG.to_string(), GIT.to_string()))
+        );
+        assert_eq!(changes.modified.len(), 1);
+    }
+
+    #[test]
+    fn plans_the_changes_since_the_base_without_an_indexed_commit() {
+        let test = TestRepo::new("base");
+        let first = test.commit(&[("git.json", GIT)], &[]);
+        let second = test.commit(&[("git.json", GIT), ("tar.json", TAR)], &[first]);
+
+        let update = plan(&test.repo, second, None, Some(&first.to_string())).unwrap();
+        let Update::Changes { base, changes } = update else {
+            panic!("expected changes");
+        };
+        assert_eq!(base, first.to_string());
+        assert_eq!(changes.added, files(&[("tar.json", TAR)]));
+        assert!(changes.removed.is_empty() && changes.modified.is_empty());
+    }
+
+    #[test]
+    fn plans_a_full_sync_without_a_commit_to_diff_against() {
+        let test = TestRepo::new("full");
+        let first = test.commit(&[("git.json", GIT)], &[]);
+        let rewritten = test.commit(&[("git.json", GIT_LOG), ("tar.json", TAR)], &[]);
+        let everything = files(&[("git.json", GIT_LOG), ("tar.json", TAR)]);
+
+        // Nothing indexed and a push that created the branch.
+        let update = plan(
+            &test.repo,
+            rewritten,
+            None,
+            Some("0000000000000000000000000000000000000000"),
+        )
+        .unwrap();
+        assert!(matches!(update, Update::Full(files) if files == everything));
+
+        // A force push replaced the indexed commit.
+        let update = plan(&test.repo, rewritten, Some(&first.to_string()), None).unwrap();
+        assert!(matches!(update, Update::Full(files) if files == everything));
+
+        // The indexed commit is not in the clone at all, the push's base is not trusted then.
+        let missing = "6113728f27ae82c7b1a177c8d03f9e96e0adf246";
+        let update = plan(
+            &test.repo,
+            rewritten,
+            Some(missing),
+            Some(&first.to_string()),
+        )
+        .unwrap();
+        assert!(matches!(update, Update::Full(files) if files == everything));
+    }
+}
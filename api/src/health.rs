@@ -0,0 +1,224 @@
+use crate::config::HealthConfig;
+use crate::open_ai;
+use crate::registry::{Registries, Registry};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// How long a dependency gets to answer before its check fails.
+static CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of one dependency check. Errors only say which dependency failed, their cause is
+/// logged.
+#[derive(Serialize, Debug, Clone)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+impl Check {
+    fn passed() -> Self {
+        Check {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn failed(error: &'static str) -> Self {
+        Check {
+            ok: false,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct EmbeddingCheck {
+    #[serde(flatten)]
+    check: Check,
+    /// Seconds since the probe ran, it is only repeated once older than the configured TTL.
+    age_secs: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct Freshness {
+    ok: bool,
+    commit: Option<String>,
+    ingested_at: Option<u64>,
+    checked_at: Option<u64>,
+    /// Seconds since the last successful check.
+    age_secs: Option<u64>,
+}
+
+impl Freshness {
+    fn unknown() -> Self {
+        Freshness {
+            ok: false,
+            commit: None,
+            ingested_at: None,
+            checked_at: None,
+            age_secs: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Readiness {
+    ready: bool,
+    /// Whether each registry's collection exists, by registry.
+    vector_store: BTreeMap<String, Check>,
+    /// Whether the embedding provider answers for each model in use, by model.
+    embedding: BTreeMap<String, EmbeddingCheck>,
+    /// How long ago each registry's source was last checked, by registry.
+    freshness: BTreeMap<String, Freshness>,
+}
+
+/// Results of probing the embedding provider by model, so readiness checks that run every few
+/// seconds do not each pay for an embedding.
+pub struct EmbeddingProbe {
+    ttl: Duration,
+    results: Mutex<HashMap<String, (Instant, Check)>>,
+}
+
+impl EmbeddingProbe {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Concurrent checks of an expired result may both probe, the lock is not held while the
+    /// provider answers.
+    async fn check(&self, model: &str) -> EmbeddingCheck {
+        if let Some((at, check)) = self.results.lock().await.get(model) {
+            if at.elapsed() < self.ttl {
+                return EmbeddingCheck {
+                    check: check.clone(),
+                    age_secs: at.elapsed().as_secs(),
+                };
+            }
+        }
+
+        let probe = tokio::time::timeout(CHECK_TIMEOUT, open_ai::embed_query(model, "spellbook"));
+        let check = match probe.await {
+            Ok(Ok(_)) => Check::passed(),
+            Ok(Err(e)) => {
+                tracing::warn!("Embedding probe of {} failed: {:#}", model, e);
+                Check::failed("Embedding request failed")
+            }
+            Err(_) => {
+                tracing::warn!("Embedding probe of {} timed out", model);
+                Check::failed("Embedding request timed out")
+            }
+        };
+        self.results
+            .lock()
+            .await
+            .insert(model.to_string(), (Instant::now(), check.clone()));
+        EmbeddingCheck { check, age_secs: 0 }
+    }
+}
+
+async fn collection_check(registry: &Registry) -> Check {
+    match tokio::time::timeout(CHECK_TIMEOUT, registry.vector_client.exists()).await {
+        Ok(Ok(true)) => Check::passed(),
+        Ok(Ok(false)) => {
+            tracing::warn!(
+                "Collection {} of {} does not exist",
+                registry.vector_client.collection(),
+                registry.name
+            );
+            Check::failed("Collection does not exist")
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("Readiness check of {} failed: {:#}", registry.name, e);
+            Check::failed("Vector store request failed")
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check of {} timed out", registry.name);
+            Check::failed("Vector store request timed out")
+        }
+    }
+}
+
+/// A polling registry is fresh unless `max_ingestion_age_secs` is set and it has not been
+/// checked successfully within that long. Other registries only hear of their source when it
+/// changes, so a quiet one is not stale.
+async fn freshness(registry: &Registry, config: &HealthConfig) -> Freshness {
+    let ledger = registry.ledger.current();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let age_secs = ledger
+        .checked_at
+        .map(|checked_at| now.saturating_sub(checked_at));
+
+    let ok = match config.max_ingestion_age_secs {
+        Some(max_age) if registry.poll.is_some() => age_secs.is_some_and(|age| age <= max_age),
+        _ => true,
+    };
+
+    Freshness {
+        ok,
+        commit: ledger.commit,
+        ingested_at: ledger.ingested_at,
+        checked_at: ledger.checked_at,
+        age_secs,
+    }
+}
+
+/// The process is up, whatever the state of its dependencies.
+pub async fn live() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Whether the server can answer searches, with a breakdown of every dependency. Answers 503
+/// when any check fails.
+pub async fn ready(
+    Extension(registries): Extension<Arc<Registries>>,
+    Extension(probe): Extension<Arc<EmbeddingProbe>>,
+    Extension(config): Extension<Arc<HealthConfig>>,
+) -> Response {
+    let mut readiness = Readiness {
+        ready: true,
+        vector_store: BTreeMap::new(),
+        embedding: BTreeMap::new(),
+        freshness: BTreeMap::new(),
+    };
+
+    for registry in registries.all() {
+        let collection = collection_check(&registry).await;
+        let freshness = tokio::time::timeout(CHECK_TIMEOUT, freshness(&registry, &config))
+            .await
+            .unwrap_or_else(|_| {
+                tracing::warn!("Freshness check of {} timed out", registry.name);
+                Freshness::unknown()
+            });
+        readiness.ready &= collection.ok && freshness.ok;
+        readiness
+            .vector_store
+            .insert(registry.name.clone(), collection);
+        readiness.freshness.insert(registry.name.clone(), freshness);
+
+        let model = registry.template.model();
+        if !readiness.embedding.contains_key(model) {
+            let embedding = probe.check(model).await;
+            readiness.ready &= embedding.check.ok;
+            readiness.embedding.insert(model.to_string(), embedding);
+        }
+    }
+
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
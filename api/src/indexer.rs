@@ -0,0 +1,349 @@
+use crate::command::{self, Command, CommandId, CompareSubCommands, SubCommand};
+use crate::open_ai::embed_command;
+use crate::template::EmbeddingTemplate;
+use crate::utils;
+use crate::vector_db::{self, VectorClient};
+use anyhow::{anyhow, Result};
+use qdrant_client::qdrant::{Condition, Filter, RetrievedPoint};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+
+/// Where public commands were indexed from, stored as their `origin` so that syncing one
+/// origin never removes the commands another one indexed.
+pub mod origin {
+    pub static REPOSITORY: &str = "repository";
+    pub static DIRECTORY: &str = "directory";
+    pub static INGEST: &str = "ingest";
+}
+
+/// Point id of one of a command's vectors. The `description` vector keeps the id a command had
+/// when it was stored as a single point, so reindexing overwrites it in place.
+pub fn point_id(command: &SubCommand, owner: Option<&str>, vector: &str) -> String {
+    let scoped = match owner {
+        Some(owner) => format!("{}/{}", owner, command.command),
+        None => command.command.clone(),
+    };
+    match vector {
+        "description" => utils::uuid_hash(&scoped),
+        _ => utils::uuid_hash(&format!("{}#{}", scoped, vector)),
+    }
+}
+
+/// Embeds and stores a command, a personal one when it has an `owner`.
+pub async fn index_command(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    command: &SubCommand,
+    owner: Option<&str>,
+    origin: Option<&str>,
+) -> Result<()> {
+    for (vector, embedding) in embed_command(template, command).await? {
+        let payload = template.payload(command, owner, origin, &vector)?;
+        vector_client
+            .insert(&point_id(command, owner, &vector), embedding, payload)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Indexes `command` in place of `previous`, which may be the same command, then deletes the
+/// points of `previous` the new ones did not overwrite, such as questions it no longer has. When
+/// indexing fails `previous` is left as it was.
+pub async fn replace_command(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    previous: &SubCommand,
+    command: &SubCommand,
+    owner: Option<&str>,
+    origin: Option<&str>,
+) -> Result<()> {
+    let filter = Filter::must([Condition::matches("command_id", previous.id(owner))]);
+    let mut leftover: HashSet<String> = vector_client
+        .scroll(Some(filter), false)
+        .await?
+        .into_iter()
+        .filter_map(|point| vector_db::point_id_to_string(point.id))
+        .collect();
+    // Points written before commands had several vectors carry no `command_id`.
+    leftover.insert(previous.id(owner));
+
+    index_command(vector_client, template, command, owner, origin).await?;
+
+    for (vector, _) in template.vectors(command) {
+        leftover.remove(&point_id(command, owner, &vector));
+    }
+    for id in leftover.iter() {
+        vector_client.delete(id).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn remove_command(
+    vector_client: &VectorClient,
+    command: &SubCommand,
+    owner: Option<&str>,
+) -> Result<()> {
+    let command_id = command.id(owner);
+    // Points written before commands had several vectors carry no `command_id`.
+    vector_client.delete(&command_id).await?;
+    vector_client.delete_command(&command_id).await?;
+
+    Ok(())
+}
+
+fn field_of(point: &RetrievedPoint, field: &str) -> Option<String> {
+    json!(point.payload)
+        .get(field)
+        .and_then(|value| value.as_str())
+        .map(String::from)
+}
+
+/// The owner's personal commands by id.
+pub async fn owned_commands(
+    vector_client: &VectorClient,
+    owner: &str,
+) -> Result<BTreeMap<String, SubCommand>> {
+    let filter = Filter::must([Condition::matches("owner", owner.to_string())]);
+
+    Ok(vector_client
+        .scroll(Some(filter), false)
+        .await?
+        .into_iter()
+        .filter_map(|point| command::from_payload(&point.payload).ok())
+        .map(|command: SubCommand| (command.id(Some(owner)), command))
+        .collect())
+}
+
+/// One of the owner's personal commands, `None` when it does not exist or belongs to someone else.
+pub async fn owned_command(
+    vector_client: &VectorClient,
+    owner: &str,
+    id: &str,
+) -> Result<Option<SubCommand>> {
+    let filter = Filter::must([
+        Condition::matches("owner", owner.to_string()),
+        Condition::matches("command_id", id.to_string()),
+    ]);
+
+    Ok(vector_client
+        .scroll(Some(filter), false)
+        .await?
+        .into_iter()
+        .find_map(|point| command::from_payload(&point.payload).ok()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct IngestSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+impl IngestSummary {
+    /// Fails when a command failed to index, so the changes are not recorded as indexed and the
+    /// next delivery or poll applies them again.
+    fn complete(self) -> Result<Self> {
+        if self.failed > 0 {
+            return Err(anyhow!(
+                "{} of {} commands failed to index",
+                self.failed,
+                self.added + self.failed
+            ));
+        }
+        Ok(self)
+    }
+}
+
+/// Removes and then indexes the given commands as coming from `origin`. A command that fails to
+/// embed is logged and counted, but does not stop the rest from being indexed.
+pub async fn apply(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    origin: &str,
+    to_add: Vec<SubCommand>,
+    to_remove: Vec<SubCommand>,
+) -> Result<IngestSummary> {
+    let mut summary = IngestSummary::default();
+
+    for command in to_remove.iter() {
+        remove_command(vector_client, command, None).await?;
+        summary.removed += 1;
+    }
+
+    for command in to_add.iter() {
+        match index_command(vector_client, template, command, None, Some(origin)).await {
+            Ok(()) => summary.added += 1,
+            Err(e) => {
+                tracing::error!("Failed to index command {}: {:#}", command.command, e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Contents of registry files by their path in the registry.
+pub type Files = BTreeMap<String, String>;
+
+/// Changed registry files by path, modified files as `(current, previous)`.
+#[derive(Debug, Default)]
+pub struct FileChanges {
+    pub added: Files,
+    pub removed: Files,
+    pub modified: BTreeMap<String, (String, String)>,
+}
+
+impl FileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+fn parse_file(path: &str, content: &str) -> Result<Vec<SubCommand>> {
+    let command = serde_json::from_str::<Command>(content)
+        .map_err(|e| anyhow!("Registry file {} is invalid: {}", path, e))?;
+    Ok(command.sub_commands())
+}
+
+/// Commands of files as they were indexed. A version that never parsed had none of its commands
+/// indexed, since changes with an invalid file are never applied.
+fn indexed_commands(path: &str, content: &str) -> Vec<SubCommand> {
+    parse_file(path, content).unwrap_or_default()
+}
+
+/// Indexes the commands of added files, removes those of removed files, and for modified files
+/// only the commands that changed. Fails without applying anything when an added or modified
+/// file does not parse, so the changes are not recorded as indexed until the file is fixed, and
+/// after applying everything else when a command fails to index.
+pub async fn apply_changes(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    origin: &str,
+    changes: &FileChanges,
+) -> Result<IngestSummary> {
+    let mut to_remove: Vec<SubCommand> = changes
+        .removed
+        .iter()
+        .flat_map(|(path, file)| indexed_commands(path, file))
+        .collect();
+
+    let mut to_add: Vec<SubCommand> = Vec::new();
+    for (path, file) in changes.added.iter() {
+        to_add.extend(parse_file(path, file)?);
+    }
+
+    for (path, (curr_file, old_file)) in changes.modified.iter() {
+        let curr_commands = parse_file(path, curr_file)?;
+        let old_commands = indexed_commands(path, old_file);
+
+        let (added, removed) = old_commands.compare(&curr_commands);
+        to_add.extend(added);
+        to_remove.extend(removed);
+    }
+
+    apply(vector_client, template, origin, to_add, to_remove)
+        .await?
+        .complete()
+}
+
+/// The public commands indexed from `origin`. Commands indexed before they were tagged with an
+/// origin are left out, so no sync ever removes them.
+pub async fn origin_commands(
+    vector_client: &VectorClient,
+    origin: &str,
+) -> Result<Vec<SubCommand>> {
+    let filter = Filter::must([
+        Condition::is_empty("owner"),
+        Condition::matches("origin", origin.to_string()),
+    ]);
+    let commands: HashSet<SubCommand> = vector_client
+        .scroll(Some(filter), false)
+        .await?
+        .into_iter()
+        .filter_map(|point| command::from_payload(&point.payload).ok())
+        .collect();
+
+    Ok(commands.into_iter().collect())
+}
+
+/// Brings the public commands indexed from `origin` in line with the full contents of its files,
+/// for sources that cannot tell what changed since they were last indexed. Commands from other
+/// origins, such as ingested ones, are left alone. Fails without applying anything when a file
+/// does not parse, and after applying everything else when a command fails to index.
+pub async fn sync(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    origin: &str,
+    files: &Files,
+) -> Result<IngestSummary> {
+    let mut commands: Vec<SubCommand> = Vec::new();
+    for (path, file) in files.iter() {
+        commands.extend(parse_file(path, file)?);
+    }
+    let indexed = origin_commands(vector_client, origin).await?;
+    let (to_add, to_remove) = indexed.compare(&commands);
+
+    apply(vector_client, template, origin, to_add, to_remove)
+        .await?
+        .complete()
+}
+
+/// Re-embeds every command with a point whose stored template version differs from the current template.
+pub async fn reindex_stale(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+) -> Result<()> {
+    let stale: HashSet<(SubCommand, Option<String>, Option<String>)> = vector_client
+        .scroll_all(false)
+        .await?
+        .into_iter()
+        .filter(|point| template.is_stale(&json!(point.payload)))
+        .filter_map(|point| {
+            let owner = field_of(&point, "owner");
+            let origin = field_of(&point, "origin");
+            Some((command::from_payload(&point.payload).ok()?, owner, origin))
+        })
+        .collect();
+
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "Reindexing {} commands for embedding template {}",
+        stale.len(),
+        template.version()
+    );
+
+    // A command that fails keeps its stale points, so it stays searchable and is retried on
+    // the next start.
+    let mut failed = 0;
+    for (command, owner, origin) in stale.iter() {
+        let reindexed = replace_command(
+            vector_client,
+            template,
+            command,
+            command,
+            owner.as_deref(),
+            origin.as_deref(),
+        )
+        .await;
+        if let Err(e) = reindexed {
+            tracing::error!("Failed to reindex command {}: {:#}", command.command, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        tracing::warn!(
+            "{} of {} stale commands were not reindexed",
+            failed,
+            stale.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::indexer::{FileChanges, Files, IngestSummary};
+use crate::metrics;
+use crate::utils;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// What the index of a registry reflects, as recorded after each ingestion.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Ledger {
+    /// Last registry commit fully applied to the index.
+    pub commit: Option<String>,
+    /// What applied the last ingestion, such as `github webhook` or `poll`.
+    pub source: Option<String>,
+    /// Seconds since the epoch the last ingestion finished at.
+    pub ingested_at: Option<u64>,
+    /// Seconds since the epoch the registry's source was last checked successfully, whether or
+    /// not anything had changed.
+    pub checked_at: Option<u64>,
+    pub last_ingestion: IngestSummary,
+    pub ingestions: u64,
+    /// Sha256 of each indexed registry file by path.
+    pub files: BTreeMap<String, String>,
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+impl Ledger {
+    fn record(&mut self, source: &str, commit: Option<&str>, summary: IngestSummary) -> Result<()> {
+        if let Some(commit) = commit {
+            self.commit = Some(commit.to_string());
+        }
+        self.source = Some(source.to_string());
+        self.ingested_at = Some(now()?);
+        self.checked_at = self.ingested_at;
+        self.last_ingestion = summary;
+        self.ingestions += 1;
+        Ok(())
+    }
+}
+
+/// A registry's ledger in `ledger.json` of its state directory. Ingestions hold its lock from
+/// reading the indexed commit until the result is written, so they apply one at a time. Readers
+/// get the last committed ledger without waiting for an ingestion in progress.
+pub struct LedgerStore {
+    registry: String,
+    path: PathBuf,
+    ledger: Mutex<Ledger>,
+    committed: RwLock<Ledger>,
+}
+
+impl LedgerStore {
+    pub fn open(registry: &str, path: PathBuf) -> Result<Self> {
+        let ledger = match fs::read(&path) {
+            Ok(body) => serde_json::from_slice(&body)
+                .map_err(|e| anyhow!("Invalid ledger {}: {}", path.display(), e))?,
+            Err(_) => Ledger::default(),
+        };
+        Ok(Self {
+            registry: registry.to_string(),
+            path,
+            committed: RwLock::new(ledger.clone()),
+            ledger: Mutex::new(ledger),
+        })
+    }
+
+    /// The last committed ledger.
+    pub fn current(&self) -> Ledger {
+        self.committed.read().unwrap().clone()
+    }
+
+    pub async fn begin(&self) -> Transaction<'_> {
+        Transaction {
+            registry: &self.registry,
+            path: &self.path,
+            ledger: self.ledger.lock().await,
+            committed: &self.committed,
+        }
+    }
+}
+
+/// An ingestion in progress, nothing is recorded unless it commits.
+pub struct Transaction<'a> {
+    registry: &'a str,
+    path: &'a PathBuf,
+    ledger: MutexGuard<'a, Ledger>,
+    committed: &'a RwLock<Ledger>,
+}
+
+impl Transaction<'_> {
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Records changed files, written through a temporary file so a crash leaves either the old
+    /// or the new ledger behind.
+    pub fn commit_changes(
+        self,
+        source: &str,
+        commit: Option<&str>,
+        changes: &FileChanges,
+        summary: IngestSummary,
+    ) -> Result<()> {
+        self.commit_with(source, commit, summary, |files| {
+            for path in changes.removed.keys() {
+                files.remove(path);
+            }
+            for (path, content) in changes.added.iter() {
+                files.insert(path.clone(), utils::sha256_hex(content));
+            }
+            for (path, (content, _)) in changes.modified.iter() {
+                files.insert(path.clone(), utils::sha256_hex(content));
+            }
+        })
+    }
+
+    /// Records that the index now holds exactly `files`.
+    pub fn commit_sync(
+        self,
+        source: &str,
+        commit: Option<&str>,
+        files: &Files,
+        summary: IngestSummary,
+    ) -> Result<()> {
+        self.commit_with(source, commit, summary, |hashes| {
+            *hashes = files
+                .iter()
+                .map(|(path, content)| (path.clone(), utils::sha256_hex(content)))
+                .collect();
+        })
+    }
+
+    /// Records an ingestion of files that are not part of the registry, such as `/ingest`.
+    pub fn commit_ingestion(self, source: &str, summary: IngestSummary) -> Result<()> {
+        self.commit_with(source, None, summary, |_| {})
+    }
+
+    /// Records that the source was checked and the index already reflects it.
+    pub fn commit_check(mut self) -> Result<()> {
+        let mut ledger = self.ledger.clone();
+        ledger.checked_at = Some(now()?);
+        self.write(ledger)
+    }
+
+    fn commit_with(
+        mut self,
+        source: &str,
+        commit: Option<&str>,
+        summary: IngestSummary,
+        update_files: impl FnOnce(&mut BTreeMap<String, String>),
+    ) -> Result<()> {
+        let mut ledger = self.ledger.clone();
+        update_files(&mut ledger.files);
+        ledger.record(source, commit, summary)?;
+
+        self.write(ledger)?;
+        metrics::record_ingestion(self.registry, source, summary);
+        Ok(())
+    }
+
+    fn write(&mut self, ledger: Ledger) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&ledger)?)?;
+        fs::rename(&temporary, self.path)?;
+
+        *self.committed.write().unwrap() = ledger.clone();
+        *self.ledger = ledger;
+        Ok(())
+    }
+}
@@ -1,173 +1,769 @@
-use anyhow::Result;
-use axum::http::StatusCode;
+use anyhow::{anyhow, Context, Result};
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::{
-    extract::Query,
+    extract::{DefaultBodyLimit, Path, Query},
+    middleware,
     response::{IntoResponse, Response},
     routing::get,
     routing::get_service,
     routing::post,
+    routing::put,
     Extension, Json, Router,
 };
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use serde_json::Value;
-use std::env;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
+use std::time::Duration;
+use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::services::ServeDir;
 use tower_http::trace::{self, TraceLayer};
 use tracing::Level;
+mod auth;
 mod command;
-mod github;
+mod config;
+mod directory;
+mod error;
+mod fill;
+mod forge;
+mod health;
+mod indexer;
+mod ledger;
+mod metrics;
 mod open_ai;
+mod placeholder;
+mod poller;
+mod registry;
+mod rerank;
+mod search;
+mod snapshot;
+mod template;
 mod utils;
 mod vector_db;
 
-use command::CompareSubCommands;
-use open_ai::{embed_command, initialize_openai};
+use auth::{ApiKey, KeyStore, Scope, User};
+use command::{CommandId, SubCommand};
+use config::{Config, ConfigArgs};
+use error::ApiError;
+use fill::{FilledCommand, PlaceholderExtractor};
+use forge::{Forge, Update};
+use open_ai::initialize_openai;
+use registry::{Registries, RegistryCtx};
+use rerank::Reranker;
+use search::SearchResult;
 use vector_db::VectorClient;
 
-struct AppError(anyhow::Error);
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+/// Metrics in the Prometheus text format, with the size of every registry's index read on each
+/// scrape.
+async fn metrics(Extension(registries): Extension<Arc<Registries>>) -> Response {
+    for registry in registries.all() {
+        match registry.vector_client.point_count().await {
+            Ok(points) => metrics::set_index_points(&registry.name, points),
+            Err(e) => tracing::warn!("Failed to count points of {}: {:#}", registry.name, e),
+        }
     }
+
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics::render(),
+    )
+        .into_response()
 }
 
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(err: E) -> Self {
-        Self(err.into())
+async fn validate(Json(payload): Json<Value>) -> Result<(StatusCode, String), ApiError> {
+    let command = serde_json::from_value::<command::Command>(payload)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+    let errors = placeholder::validate(&command);
+    if !errors.is_empty() {
+        return Err(ApiError::InvalidCommand(errors));
     }
+
+    Ok((StatusCode::OK, "Valid".to_string()))
 }
 
-async fn health() -> StatusCode {
-    StatusCode::OK
+#[derive(serde::Deserialize)]
+struct RenderPayload {
+    command: command::SubCommand,
+    #[serde(default)]
+    values: HashMap<String, String>,
 }
 
-async fn validate(Json(payload): Json<Value>) -> (StatusCode, String) {
-    match serde_json::from_value::<command::Command>(payload) {
-        Ok(_) => (StatusCode::OK, "Valid".to_string()),
-        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()),
-    }
+async fn render(Json(payload): Json<RenderPayload>) -> Result<Json<Value>, ApiError> {
+    let command =
+        placeholder::render(&payload.command, &payload.values).map_err(ApiError::InvalidCommand)?;
+    Ok(Json(json!({ "command": command })))
 }
 
+/// Applies a push from GitHub, GitLab, Gitea, Forgejo or Bitbucket to the registry. The push is
+/// accepted when signed with the registry's webhook secret, or otherwise with an API key that has
+/// the admin scope.
 async fn process_webhook(
-    Extension(vector_client): Extension<Arc<VectorClient>>,
-    Json(payload): Json<github::PushWebhookPayload>,
-) -> Result<StatusCode, AppError> {
-    let result = github::process_payload(payload)?;
-
-    let mut commands_to_remove: Vec<command::SubCommand> = result
-        .removed
-        .iter()
-        .flat_map(|file| {
-            let command = serde_json::from_str::<command::Command>(&file).unwrap();
-            return command.commands;
-        })
-        .collect();
-
-    let mut commands_to_add: Vec<command::SubCommand> = result
-        .added
-        .iter()
-        .flat_map(|file| {
-            let command = serde_json::from_str::<command::Command>(&file).unwrap();
-            return command.commands;
-        })
-        .collect();
-
-    for (curr_file, old_file) in result.modified.iter() {
-        let curr_command = serde_json::from_str::<command::Command>(&curr_file).unwrap();
-        let old_command = serde_json::from_str::<command::Command>(&old_file).unwrap();
-
-        let (to_add, to_remove) = curr_command.commands.compare(&old_command.commands);
-
-        commands_to_add.extend(to_add);
-        commands_to_remove.extend(to_remove);
+    RegistryCtx(registry): RegistryCtx,
+    key: Option<Extension<ApiKey>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let Some(forge) = Forge::detect(&headers) else {
+        return Err(ApiError::BadRequest(
+            "Unrecognized webhook sender".to_string(),
+        ));
+    };
+    let key = key.map(|Extension(key)| key);
+    registry::authorize_webhook(&registry, forge, key.as_ref(), &headers, &body)?;
+    if !forge.is_push(&headers) {
+        return Ok((StatusCode::OK, "Ignored event that is not a push").into_response());
     }
 
-    let commands_to_remove_count = commands_to_remove.len();
-    let commands_to_add_count = commands_to_add.len();
-
-    for command in commands_to_remove.into_iter() {
-        let id = utils::uuid_hash(&command.command.clone());
-        vector_client.delete(&id).await?;
+    let event = forge
+        .parse_push(&body, &registry.branch)
+        .map_err(ApiError::bad_input)?;
+    if !registry.tracks(&event.full_name) {
+        return Err(ApiError::BadRequest(format!(
+            "Registry {} does not track {}",
+            registry.name, event.full_name
+        )));
+    }
+    if event.branch() != Some(registry.branch.as_str()) {
+        return Ok((
+            StatusCode::OK,
+            format!(
+                "Ignored push to {}, only {} is indexed",
+                event.git_ref, registry.branch
+            ),
+        )
+            .into_response());
     }
+    if event.deletes_branch() {
+        return Ok((StatusCode::OK, "Ignored branch deletion").into_response());
+    }
+
+    // Deliveries are applied one at a time, each from the commit the index reflects.
+    let transaction = registry.ledger.begin().await;
+    let indexed = transaction.ledger().commit.clone();
+    // Cloning blocks, it runs off the runtime like the poller's fetch.
+    let update = tokio::task::spawn_blocking({
+        let event = event.clone();
+        let clone_path = registry.clone_path();
+        let indexed = indexed.clone();
+        move || forge::process_push(&event, &clone_path, indexed.as_deref())
+    })
+    .await
+    .map_err(|e| anyhow!("Cloning panicked: {}", e))??;
 
-    for command in commands_to_add.into_iter() {
-        let id = utils::uuid_hash(&command.command.clone());
-        let payload = serde_json::to_value(&command).unwrap().try_into().unwrap();
-        if let Ok(embedding) = embed_command(command).await {
-            vector_client.insert(&id, embedding, payload).await?;
+    match &update {
+        Update::Unchanged => {
+            transaction.commit_check()?;
+            return Ok((StatusCode::OK, "Commit is already indexed").into_response());
+        }
+        Update::Behind => {
+            tracing::warn!(
+                "Rejected out of order push of {} to {}, {} is already indexed",
+                event.after,
+                registry.name,
+                indexed.as_deref().unwrap_or_default()
+            );
+            return Err(ApiError::Conflict(format!(
+                "{} is older than the indexed commit {}",
+                event.after,
+                indexed.unwrap_or_default()
+            )));
+        }
+        Update::Changes { base, .. } if *base != event.before => {
+            tracing::info!(
+                "Reconciling {} from the indexed commit {} rather than {}",
+                registry.name,
+                base,
+                event.before
+            );
         }
+        Update::Full(_) if indexed.is_some() => {
+            tracing::info!(
+                "Resyncing {}, its indexed commit is not in the history of {}",
+                registry.name,
+                event.after
+            );
+        }
+        _ => {}
     }
 
+    let source = format!("{} webhook", forge.name());
+    let summary = registry
+        .apply_update(transaction, &source, &event.after, update)
+        .await?;
+
     tracing::info!(
-        "Processed webhook: {} commands added, {} commands removed",
-        commands_to_add_count,
-        commands_to_remove_count
+        "Processed {} webhook for {}: {} commands added, {} commands removed",
+        forge.name(),
+        registry.name,
+        summary.added,
+        summary.removed
     );
 
-    Ok(StatusCode::OK)
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Indexes registry files sent directly, such as pages converted by `spellbook tldr --ingest`.
+async fn ingest(
+    RegistryCtx(registry): RegistryCtx,
+    Json(commands): Json<Vec<command::Command>>,
+) -> Result<(StatusCode, Json<indexer::IngestSummary>), ApiError> {
+    let commands_to_add = commands
+        .into_iter()
+        .flat_map(|command| command.sub_commands())
+        .collect();
+
+    let transaction = registry.ledger.begin().await;
+    let summary = indexer::apply(
+        &registry.vector_client,
+        &registry.template,
+        indexer::origin::INGEST,
+        commands_to_add,
+        Vec::new(),
+    )
+    .await?;
+    transaction.commit_ingestion("ingest", summary)?;
+
+    tracing::info!("Ingested {} commands into {}", summary.added, registry.name);
+
+    Ok((StatusCode::OK, Json(summary)))
+}
+
+#[derive(serde::Serialize)]
+struct Status {
+    registry: String,
+    collection: String,
+    #[serde(flatten)]
+    ledger: ledger::Ledger,
 }
 
+/// Which registry commit the index reflects and how the last ingestion went.
+async fn status(RegistryCtx(registry): RegistryCtx) -> Json<Status> {
+    Json(Status {
+        registry: registry.name.clone(),
+        collection: registry.vector_client.collection().to_string(),
+        ledger: registry.ledger.current(),
+    })
+}
+
+static SEARCH_LIMIT: usize = 5;
+
 #[derive(serde::Deserialize)]
 struct SearchQueryParams {
     query: String,
 }
 
 async fn search(
-    Extension(vector_client): Extension<Arc<VectorClient>>,
+    RegistryCtx(registry): RegistryCtx,
+    Extension(reranker): Extension<Option<Arc<dyn Reranker>>>,
+    user: Option<User>,
     Query(query): Query<SearchQueryParams>,
-) -> Result<(StatusCode, Json<Vec<command::SubCommand>>), AppError> {
-    let embedded_query = open_ai::embed_query(&query.query).await.unwrap();
+) -> Result<(StatusCode, Json<Vec<SearchResult>>), ApiError> {
+    let results = search::search(
+        &registry,
+        reranker.as_deref(),
+        &query.query,
+        SEARCH_LIMIT,
+        user.as_ref().map(|User(name)| name.as_str()),
+    )
+    .await?;
+    metrics::record_search(&registry.name, results.len());
+
+    Ok((StatusCode::OK, Json(results)))
+}
+
+#[derive(serde::Deserialize)]
+struct SearchAllQueryParams {
+    query: String,
+    /// Comma separated registry names, every registry when unset.
+    registries: Option<String>,
+}
 
-    let search_result = vector_client.search(embedded_query, 5).await?;
+/// Searches several registries at once, each result names the registry it comes from.
+async fn search_all(
+    Extension(registries): Extension<Arc<Registries>>,
+    Extension(reranker): Extension<Option<Arc<dyn Reranker>>>,
+    user: Option<User>,
+    Query(query): Query<SearchAllQueryParams>,
+) -> Result<(StatusCode, Json<Vec<SearchResult>>), ApiError> {
+    let selected = match &query.registries {
+        None => registries.all(),
+        Some(names) => {
+            let mut selected = Vec::new();
+            for name in names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                let registry = registries
+                    .get(name)
+                    .ok_or_else(|| ApiError::NotFound(format!("No registry named {}", name)))?;
+                selected.push(registry);
+            }
+            selected
+        }
+    };
+
+    let results = search::search_all(
+        &selected,
+        reranker.clone(),
+        &query.query,
+        SEARCH_LIMIT,
+        user.as_ref().map(|User(name)| name.as_str()),
+    )
+    .await?;
+    metrics::record_search(metrics::ALL_REGISTRIES, results.len());
+
+    Ok((StatusCode::OK, Json(results)))
+}
 
-    let sub_commands: Vec<command::SubCommand> = search_result
-        .result
+async fn fill(
+    RegistryCtx(registry): RegistryCtx,
+    Extension(reranker): Extension<Option<Arc<dyn Reranker>>>,
+    Extension(extractor): Extension<Arc<dyn PlaceholderExtractor>>,
+    user: Option<User>,
+    Query(query): Query<SearchQueryParams>,
+) -> Result<(StatusCode, Json<FilledCommand>), ApiError> {
+    let results = search::search(
+        &registry,
+        reranker.as_deref(),
+        &query.query,
+        1,
+        user.as_ref().map(|User(name)| name.as_str()),
+    )
+    .await?;
+
+    let result = results
         .into_iter()
-        .filter_map(|result| match result.try_into() {
-            Ok(sub_command) => Some(sub_command),
-            Err(_) => None,
-        })
-        .collect();
+        .next()
+        .ok_or_else(|| ApiError::NotFound("No command matches the query".to_string()))?;
+
+    let values = extractor.extract(&query.query, &result.command).await?;
+
+    Ok((StatusCode::OK, Json(FilledCommand::new(result, values))))
+}
+
+/// Checks a personal command's placeholders like `/validate` does for registry files.
+fn validate_personal(command: &SubCommand) -> Result<(), ApiError> {
+    let file = command::Command {
+        name: command.tool.clone().unwrap_or_default(),
+        commands: vec![command.clone()],
+    };
+    let errors = placeholder::validate(&file);
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    Err(ApiError::InvalidCommand(errors))
+}
+
+/// Also matches routes nested under `/r/:registry`, whose extra parameter is ignored.
+#[derive(serde::Deserialize)]
+struct CommandPath {
+    id: String,
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::NotFound(format!("No command {} of yours", id))
+}
+
+async fn list_commands(
+    RegistryCtx(registry): RegistryCtx,
+    User(user): User,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let commands = indexer::owned_commands(&registry.vector_client, &user).await?;
+    Ok((StatusCode::OK, Json(json!(commands))))
+}
+
+/// Adds a personal command, only ever returned in the owner's searches. Its id is derived from
+/// the command, so creating the same command twice updates it.
+async fn create_command(
+    RegistryCtx(registry): RegistryCtx,
+    User(user): User,
+    Json(command): Json<SubCommand>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    validate_personal(&command)?;
+
+    // Replaces the points of an earlier copy, which may have had more questions.
+    indexer::replace_command(
+        &registry.vector_client,
+        &registry.template,
+        &command,
+        &command,
+        Some(&user),
+        None,
+    )
+    .await?;
+    tracing::info!("{} added personal command {}", user, command.command);
 
-    Ok((StatusCode::OK, Json(sub_commands)))
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "id": command.id(Some(&user)), "command": command })),
+    ))
+}
+
+/// Replaces a personal command, the response carries its new id when the command text changed.
+async fn update_command(
+    RegistryCtx(registry): RegistryCtx,
+    User(user): User,
+    Path(CommandPath { id }): Path<CommandPath>,
+    Json(command): Json<SubCommand>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let Some(existing) = indexer::owned_command(&registry.vector_client, &user, &id).await? else {
+        return Err(not_found(&id));
+    };
+    validate_personal(&command)?;
+
+    indexer::replace_command(
+        &registry.vector_client,
+        &registry.template,
+        &existing,
+        &command,
+        Some(&user),
+        None,
+    )
+    .await?;
+    tracing::info!("{} updated personal command {}", user, command.command);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "id": command.id(Some(&user)), "command": command })),
+    ))
+}
+
+async fn delete_command(
+    RegistryCtx(registry): RegistryCtx,
+    User(user): User,
+    Path(CommandPath { id }): Path<CommandPath>,
+) -> Result<Response, ApiError> {
+    let Some(existing) = indexer::owned_command(&registry.vector_client, &user, &id).await? else {
+        return Err(not_found(&id));
+    };
+
+    indexer::remove_command(&registry.vector_client, &existing, Some(&user)).await?;
+    tracing::info!("{} deleted personal command {}", user, existing.command);
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct ExportQueryParams {
+    #[serde(default)]
+    vectors: bool,
+    format: Option<String>,
+}
+
+/// The public registry as JSON, or with `format=jsonl` a full backup including personal
+/// commands, which needs an admin key.
+async fn export(
+    RegistryCtx(registry): RegistryCtx,
+    key: Option<Extension<ApiKey>>,
+    Query(params): Query<ExportQueryParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (body, content_type) = match params.format.as_deref() {
+        Some("jsonl") => {
+            match key {
+                None => {
+                    return Err(ApiError::Unauthorized(
+                        "Backups need an admin API key".to_string(),
+                    ))
+                }
+                Some(Extension(key)) if !key.allows(Scope::Admin) => {
+                    return Err(ApiError::Forbidden(
+                        "Backups need an admin API key".to_string(),
+                    ))
+                }
+                Some(_) => {}
+            }
+            let snapshot =
+                snapshot::build(&registry.vector_client, &registry.template, true, true).await?;
+            (snapshot.to_jsonl()?.into_bytes(), "application/x-ndjson")
+        }
+        _ => {
+            let snapshot = snapshot::build(
+                &registry.vector_client,
+                &registry.template,
+                params.vectors,
+                false,
+            )
+            .await?;
+            (serde_json::to_vec(&snapshot)?, "application/json")
+        }
+    };
+    let etag = format!("\"{}\"", utils::sha256_hex(&body));
+
+    let if_none_match = headers.get(header::IF_NONE_MATCH);
+    if if_none_match.is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+        return Ok(StatusCode::NOT_MODIFIED.into_response());
+    }
+
+    Ok((
+        [
+            (header::ETAG, etag),
+            (header::CONTENT_TYPE, content_type.to_string()),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Snapshots carry every vector, so they are much larger than the default body limit.
+static IMPORT_BODY_LIMIT: usize = 512 * 1024 * 1024;
+
+#[derive(serde::Deserialize)]
+struct ImportQueryParams {
+    #[serde(default)]
+    overwrite: bool,
+}
+
+async fn import(
+    RegistryCtx(registry): RegistryCtx,
+    Extension(keys): Extension<Arc<KeyStore>>,
+    Query(params): Query<ImportQueryParams>,
+    body: String,
+) -> Result<(StatusCode, Json<snapshot::ImportSummary>), ApiError> {
+    let users: HashSet<String> = keys.keys().into_iter().filter_map(|key| key.user).collect();
+    let summary = snapshot::import(
+        &registry.vector_client,
+        &registry.template,
+        &body,
+        params.overwrite,
+        &users,
+    )
+    .await
+    .map_err(ApiError::bad_input)?;
+
+    tracing::info!(
+        "Imported snapshot into {}: {} points imported, {} skipped",
+        registry.name,
+        summary.imported,
+        summary.skipped
+    );
+
+    Ok((StatusCode::OK, Json(summary)))
+}
+
+#[derive(Parser)]
+#[command(name = "spellbook-server", about = "Semantic command search server")]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<ServerCommand>,
+}
+
+#[derive(Subcommand)]
+enum ServerCommand {
+    /// Start the server, the default when no command is given
+    Serve,
+    /// Manage the API keys in `API_KEYS_FILE`
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the resolved configuration with secrets redacted and report what is missing
+    Check,
+}
+
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Create a key and print it, it cannot be shown again
+    Mint {
+        /// What the key is for, such as `github-webhook`
+        #[arg(long)]
+        name: String,
+        /// Scopes to grant, `write` includes `search` and `admin` includes both
+        #[arg(long = "scope", value_enum, required = true)]
+        scopes: Vec<Scope>,
+        /// User whose personal commands the key manages
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Delete a key by its id
+    Revoke { id: String },
+    /// Show every key without its secret
+    List,
+}
+
+fn keys(config: &Config, action: KeysAction) -> Result<()> {
+    let store = KeyStore::open(config.server.api_keys_file.clone())?;
+
+    match action {
+        KeysAction::Mint { name, scopes, user } => {
+            let (key, secret) = store.mint(&name, scopes, user)?;
+            eprintln!("Minted API key {} ({})", key.id, key.name);
+            println!("{}", secret);
+        }
+        KeysAction::Revoke { id } => {
+            let key = store.revoke(&id)?;
+            eprintln!("Revoked API key {} ({})", key.id, key.name);
+        }
+        KeysAction::List => {
+            for key in store.keys() {
+                let scopes: Vec<&str> = key.scopes.iter().map(|scope| scope.name()).collect();
+                println!(
+                    "{}  {}  {}  {}",
+                    key.id,
+                    key.name,
+                    scopes.join(","),
+                    key.user.as_deref().unwrap_or("-")
+                );
+            }
+        }
+    }
+
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
-    let vector_client = VectorClient::new(
-        &env::var("QDRANT_URL").unwrap(),
-        &env::var("QDRANT_TOKEN").unwrap(),
-    )
-    .await
-    .unwrap();
-    let vector_client = Arc::new(vector_client);
-    initialize_openai(env::var("OPENAI_TOKEN").unwrap()).unwrap();
-    let cors = CorsLayer::new().allow_origin(Any).allow_methods(Any);
+
+    let args = Args::parse();
+    match args.command {
+        Some(ServerCommand::Keys { action }) => keys(&Config::load(&args.config)?, action),
+        Some(ServerCommand::Config {
+            action: ConfigAction::Check,
+        }) => {
+            let config = Config::load(&args.config)?;
+            print!("{}", config.to_redacted_toml()?);
+            config.check()
+        }
+        Some(ServerCommand::Serve) | None => serve(Config::load(&args.config)?).await,
+    }
+}
+
+/// Routes served for a single registry, at the root for the default registry and under
+/// `/r/:registry` for every registry.
+fn registry_routes(public_search: bool) -> Router {
+    let mut search_routes = Router::new()
+        .route("/search", get(search))
+        .route("/fill", get(fill))
+        .route("/export", get(export))
+        .route("/status", get(status));
+    if !public_search {
+        search_routes = search_routes.route_layer(middleware::from_fn(auth::require_search));
+    }
+
+    let write_routes = Router::new()
+        .route("/commands", get(list_commands).post(create_command))
+        .route("/commands/:id", put(update_command).delete(delete_command))
+        .route_layer(middleware::from_fn(auth::require_write));
+
+    let admin_routes = Router::new()
+        .route(
+            "/import",
+            post(import).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
+        .route("/ingest", post(ingest))
+        .route_layer(middleware::from_fn(auth::require_admin));
+
+    Router::new()
+        // Webhooks authorize themselves, GitHub signs them rather than sending an API key.
+        .route("/webhook", post(process_webhook))
+        .merge(search_routes)
+        .merge(write_routes)
+        .merge(admin_routes)
+}
+
+async fn serve(config: Config) -> Result<()> {
+    config.check()?;
+    let (Some(url), Some(token), Some(api_key)) = (
+        &config.vector_store.url,
+        &config.vector_store.token,
+        &config.embedding.api_key,
+    ) else {
+        return Err(anyhow!("Missing required settings"));
+    };
+    let vector_client = VectorClient::new(url, token.expose()).await?;
+    initialize_openai(api_key.expose().to_string())?;
+    let registries = Arc::new(Registries::new(&vector_client, &config)?);
+    let reranker = rerank::configured(&config.rerank);
+    let extractor = fill::configured(&config.fill);
+    let keys = Arc::new(KeyStore::open(config.server.api_keys_file.clone())?);
+    let public_search = config.server.public_search;
+    let probe = Arc::new(health::EmbeddingProbe::new(Duration::from_secs(
+        config.health.embedding_probe_ttl_secs,
+    )));
+    let health = Arc::new(config.health.clone());
+    let cors = config.cors()?;
 
     tracing_subscriber::fmt()
         .with_target(false)
         .compact()
         .init();
+    error::log_panics();
+
+    tokio::spawn(keys.clone().watch());
+    for registry in registries.all() {
+        tokio::spawn(async move {
+            if let Err(e) = registry.ensure_collection().await {
+                tracing::error!("Failed to create collection for {}: {:#}", registry.name, e);
+            } else if let Err(e) =
+                indexer::reindex_stale(&registry.vector_client, &registry.template).await
+            {
+                tracing::error!(
+                    "Failed to reindex stale commands of {}: {:#}",
+                    registry.name,
+                    e
+                );
+            }
+            if let Some(config) = registry.poll.clone() {
+                tokio::spawn(poller::poll(registry.clone(), config));
+            }
+            if let Some(dir) = registry.directory.clone() {
+                if let Err(e) = directory::watch(registry.clone(), dir).await {
+                    tracing::error!(
+                        "Stopped watching the directory of {}: {:#}",
+                        registry.name,
+                        e
+                    );
+                }
+            }
+        });
+    }
+
+    // Metrics name registries and reveal how they are searched, so they are as public as search.
+    let mut search_routes = Router::new()
+        .route("/search/all", get(search_all))
+        .route("/metrics", get(metrics));
+    if !public_search {
+        search_routes = search_routes.route_layer(middleware::from_fn(auth::require_search));
+    }
 
     let router = Router::new()
-        .nest_service("/", get_service(ServeDir::new("dist")))
-        .route("/health", get(health))
+        .nest_service("/", get_service(ServeDir::new(&config.server.static_dir)))
+        .route("/health", get(health::live))
+        .route("/health/live", get(health::live))
+        .route("/health/ready", get(health::ready))
         .route("/validate", post(validate))
-        .route("/search", get(search))
-        .route("/webhook", post(process_webhook))
-        .layer(Extension(vector_client))
+        .route("/render", post(render))
+        .merge(search_routes)
+        .merge(registry_routes(public_search))
+        .nest("/r/:registry", registry_routes(public_search))
+        .layer(middleware::from_fn(auth::authenticate))
+        .layer(Extension(registries))
+        .layer(Extension(probe))
+        .layer(Extension(health))
+        .layer(Extension(reranker))
+        .layer(Extension(extractor))
+        .layer(Extension(keys))
         .layer(cors)
+        .layer(CatchPanicLayer::custom(error::panic_response))
+        .layer(middleware::from_fn(error::request_id))
+        .layer(middleware::from_fn(metrics::track))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
@@ -176,9 +772,12 @@ async fn main() -> Result<()> {
                 .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
         );
 
-    tracing::info!("Spellbook server started on port: 8080");
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, router).await.unwrap();
+    let bind = &config.server.bind;
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("Failed to bind {}", bind))?;
+    tracing::info!("Spellbook server started on {}", bind);
+    axum::serve(listener, router).await?;
 
     Ok(())
 }
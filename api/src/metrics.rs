@@ -0,0 +1,250 @@
+use crate::indexer::IngestSummary;
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// Buckets of the number of results a search returned.
+static RESULT_BUCKETS: [f64; 7] = [0.0, 1.0, 2.0, 3.0, 5.0, 10.0, 20.0];
+
+/// Everything `/metrics` exposes, kept in a registry of its own.
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    embedding_duration: HistogramVec,
+    embedding_errors: IntCounterVec,
+    vector_store_duration: HistogramVec,
+    vector_store_errors: IntCounterVec,
+    searches: IntCounterVec,
+    empty_searches: IntCounterVec,
+    search_results: HistogramVec,
+    ingested_commands: IntCounterVec,
+    index_points: IntGaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// Names and labels are fixed, so creating and registering the metrics cannot fail.
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+    counter
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(gauge.clone())).unwrap();
+    gauge
+}
+
+fn histogram(
+    registry: &Registry,
+    name: &str,
+    help: &str,
+    labels: &[&str],
+    buckets: Option<&[f64]>,
+) -> HistogramVec {
+    let mut opts = HistogramOpts::new(name, help);
+    if let Some(buckets) = buckets {
+        opts = opts.buckets(buckets.to_vec());
+    }
+    let histogram = HistogramVec::new(opts, labels).unwrap();
+    registry.register(Box::new(histogram.clone())).unwrap();
+    histogram
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("spellbook".to_string()), None).unwrap();
+
+        Self {
+            http_requests: counter(
+                &registry,
+                "http_requests_total",
+                "HTTP requests by route and status",
+                &["method", "route", "status"],
+            ),
+            http_duration: histogram(
+                &registry,
+                "http_request_duration_seconds",
+                "Time taken to answer HTTP requests by route",
+                &["method", "route"],
+                None,
+            ),
+            embedding_duration: histogram(
+                &registry,
+                "embedding_request_duration_seconds",
+                "Time taken by embedding requests by provider",
+                &["provider"],
+                None,
+            ),
+            embedding_errors: counter(
+                &registry,
+                "embedding_errors_total",
+                "Failed embedding requests by provider",
+                &["provider"],
+            ),
+            vector_store_duration: histogram(
+                &registry,
+                "vector_store_request_duration_seconds",
+                "Time taken by vector store requests by operation",
+                &["operation"],
+                None,
+            ),
+            vector_store_errors: counter(
+                &registry,
+                "vector_store_errors_total",
+                "Failed vector store requests by operation",
+                &["operation"],
+            ),
+            searches: counter(
+                &registry,
+                "searches_total",
+                "Searches by registry",
+                &["registry"],
+            ),
+            empty_searches: counter(
+                &registry,
+                "searches_without_results_total",
+                "Searches that found nothing by registry",
+                &["registry"],
+            ),
+            search_results: histogram(
+                &registry,
+                "search_results",
+                "Number of results returned by searches by registry",
+                &["registry"],
+                Some(&RESULT_BUCKETS),
+            ),
+            ingested_commands: counter(
+                &registry,
+                "ingested_commands_total",
+                "Commands added, removed or failed by ingestions by registry and source",
+                &["registry", "source", "outcome"],
+            ),
+            index_points: gauge(
+                &registry,
+                "index_points",
+                "Points stored in each registry's collection",
+                &["registry"],
+            ),
+            registry,
+        }
+    }
+}
+
+/// Counts and times every request by the route it matched, so `/r/:registry/search` is one
+/// series however many registries there are.
+pub async fn track(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    METRICS
+        .http_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
+
+/// Times an embedding request to `provider`, counting it when it fails.
+pub async fn embedding<T, E>(
+    provider: &str,
+    request: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = request.await;
+
+    METRICS
+        .embedding_duration
+        .with_label_values(&[provider])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        METRICS
+            .embedding_errors
+            .with_label_values(&[provider])
+            .inc();
+    }
+    result
+}
+
+/// Times a vector store request, counting it when it fails.
+pub async fn vector_store<T, E>(
+    operation: &str,
+    request: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = request.await;
+
+    METRICS
+        .vector_store_duration
+        .with_label_values(&[operation])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        METRICS
+            .vector_store_errors
+            .with_label_values(&[operation])
+            .inc();
+    }
+    result
+}
+
+/// Registry label of searches across registries, which no registry name can take.
+pub static ALL_REGISTRIES: &str = "*";
+
+/// Counts a search answered by `/search` or `/search/all`, `/fill` only looks for the best match.
+pub fn record_search(registry: &str, results: usize) {
+    METRICS.searches.with_label_values(&[registry]).inc();
+    METRICS
+        .search_results
+        .with_label_values(&[registry])
+        .observe(results as f64);
+    if results == 0 {
+        METRICS.empty_searches.with_label_values(&[registry]).inc();
+    }
+}
+
+pub fn record_ingestion(registry: &str, source: &str, summary: IngestSummary) {
+    for (outcome, count) in [
+        ("added", summary.added),
+        ("removed", summary.removed),
+        ("failed", summary.failed),
+    ] {
+        METRICS
+            .ingested_commands
+            .with_label_values(&[registry, source, outcome])
+            .inc_by(count as u64);
+    }
+}
+
+pub fn set_index_points(registry: &str, points: u64) {
+    METRICS
+        .index_points
+        .with_label_values(&[registry])
+        .set(points as i64);
+}
+
+/// Every metric in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&METRICS.registry.gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {}", e);
+    }
+    String::from_utf8_lossy(&buffer).to_string()
+}
@@ -1,21 +1,72 @@
 use crate::command::SubCommand;
-use anyhow::{Error, Result};
+use crate::error::Upstream;
+use crate::metrics;
+use crate::template::EmbeddingTemplate;
+use anyhow::{Context, Error, Result};
+use openai::chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole};
 use openai::embeddings::Embedding;
 
+/// Label of this provider's embedding metrics.
+static PROVIDER: &str = "openai";
+
+/// Model used unless a registry configures another one.
+pub static EMBEDDING_MODEL: &str = "text-embedding-ada-002";
+
 pub fn initialize_openai(token: String) -> Result<()> {
     openai::set_key(token);
     Ok(())
 }
 
-pub async fn embed_command(command: SubCommand) -> Result<Embedding, Error> {
-    let embedding =
-        Embedding::create("text-embedding-ada-002", &command.to_string(), "spellbook").await?;
+pub async fn embed_command(
+    template: &EmbeddingTemplate,
+    command: &SubCommand,
+) -> Result<Vec<(String, Embedding)>, Error> {
+    let mut embeddings = Vec::new();
 
-    Ok(embedding)
+    for (vector, text) in template.vectors(command) {
+        let request = Embedding::create(template.model(), &text, "spellbook");
+        let embedding = metrics::embedding(PROVIDER, request)
+            .await
+            .context(Upstream::Embedding)?;
+        embeddings.push((vector, embedding));
+    }
+
+    Ok(embeddings)
 }
 
-pub async fn embed_query(query: &str) -> Result<Embedding, Error> {
-    let embedding = Embedding::create("text-embedding-ada-002", query, "spellbook").await?;
+pub async fn embed_query(model: &str, query: &str) -> Result<Embedding, Error> {
+    let embedding = metrics::embedding(PROVIDER, Embedding::create(model, query, "spellbook"))
+        .await
+        .context(Upstream::Embedding)?;
 
     Ok(embedding)
 }
+
+pub async fn complete(model: &str, system: &str, prompt: &str) -> Result<String, Error> {
+    let message = |role, content: &str| ChatCompletionMessage {
+        role,
+        content: Some(content.to_string()),
+        name: None,
+        function_call: None,
+    };
+    let messages = vec![
+        message(ChatCompletionMessageRole::System, system),
+        message(ChatCompletionMessageRole::User, prompt),
+    ];
+
+    let completion = ChatCompletion::builder(model, messages)
+        .temperature(0.0)
+        .user("spellbook")
+        .create()
+        .await
+        .context(Upstream::Completion)?;
+
+    let content = completion
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .unwrap_or_default();
+
+    Ok(content)
+}
@@ -0,0 +1,283 @@
+use crate::command::{Command, Placeholder, SubCommand};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlaceholderError {
+    pub command: String,
+    pub placeholder: String,
+    pub message: String,
+}
+
+impl fmt::Display for PlaceholderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: placeholder {}: {}",
+            self.command, self.placeholder, self.message
+        )
+    }
+}
+
+/// Checks that the placeholders declared in a registry file are consistent with their commands.
+pub fn validate(command: &Command) -> Vec<PlaceholderError> {
+    let mut errors = Vec::new();
+
+    for sub_command in command.commands.iter() {
+        let used = sub_command.placeholder_names();
+        let mut declared = HashSet::new();
+        let mut error = |placeholder: &str, message: String| {
+            errors.push(PlaceholderError {
+                command: sub_command.command.clone(),
+                placeholder: placeholder.to_string(),
+                message,
+            })
+        };
+
+        for placeholder in sub_command.placeholders.iter().flatten() {
+            let name = placeholder.name.as_str();
+
+            if !declared.insert(name) {
+                error(name, "declared more than once".to_string());
+            }
+            if !used.iter().any(|used| used == name) {
+                error(name, "declared but not used in the command".to_string());
+            }
+            let mut checked = placeholder.clone();
+            if let Some(pattern) = &placeholder.pattern {
+                if let Err(e) = Regex::new(pattern) {
+                    error(name, format!("invalid pattern: {}", e));
+                    // Reported once, the default is only checked against the rest.
+                    checked.pattern = None;
+                }
+            }
+            if placeholder
+                .choices
+                .as_ref()
+                .is_some_and(|choices| choices.is_empty())
+            {
+                error(name, "choices must not be empty".to_string());
+            }
+            if let Some(default) = &placeholder.default {
+                if let Err(e) = checked.check(default) {
+                    error(name, format!("invalid default: {}", e));
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+/// Values for every placeholder of the command, falling back to declared defaults and leaving
+/// optional placeholders empty. Missing required values and invalid values are reported.
+pub fn resolve(
+    command: &SubCommand,
+    values: &HashMap<String, String>,
+) -> (HashMap<String, String>, Vec<PlaceholderError>) {
+    let declared: HashMap<&str, &Placeholder> = command
+        .placeholders
+        .iter()
+        .flatten()
+        .map(|placeholder| (placeholder.name.as_str(), placeholder))
+        .collect();
+    let mut resolved = HashMap::new();
+    let mut errors = Vec::new();
+
+    for name in command.placeholder_names() {
+        let placeholder = declared.get(name.as_str());
+        let value = values
+            .get(&name)
+            .cloned()
+            .or_else(|| placeholder.and_then(|placeholder| placeholder.default.clone()));
+
+        let result = match (value, placeholder) {
+            (Some(value), Some(placeholder)) => placeholder.check(&value).map(|_| value),
+            (Some(value), None) => Ok(value),
+            (None, Some(placeholder)) if placeholder.is_optional() => Ok(String::new()),
+            (None, _) => Err("a value is required".to_string()),
+        };
+
+        match result {
+            Ok(value) => {
+                resolved.insert(name, value);
+            }
+            Err(message) => errors.push(PlaceholderError {
+                command: command.command.clone(),
+                placeholder: name,
+                message,
+            }),
+        }
+    }
+
+    (resolved, errors)
+}
+
+/// Stands in for an omitted placeholder while rendering, so the spaces around it can be
+/// collapsed without touching any other spaces of the command.
+static OMITTED: &str = "\u{0}";
+
+/// Renders the command with all of its placeholders substituted, dropping the space left by
+/// each omitted optional placeholder so `ls {flags} /tmp` renders as `ls /tmp`.
+pub fn render(
+    command: &SubCommand,
+    values: &HashMap<String, String>,
+) -> Result<String, Vec<PlaceholderError>> {
+    let (mut resolved, errors) = resolve(command, values);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    for value in resolved.values_mut().filter(|value| value.is_empty()) {
+        *value = OMITTED.to_string();
+    }
+    let mut rendered = String::new();
+    for piece in command.substitute(&resolved).split(OMITTED) {
+        if rendered.ends_with(' ') && piece.starts_with(' ') {
+            rendered.truncate(rendered.trim_end_matches(' ').len());
+        }
+        rendered.push_str(piece);
+    }
+
+    Ok(rendered.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn command(command: &str, placeholders: Value) -> SubCommand {
+        serde_json::from_value(json!({
+            "command": command,
+            "description": "",
+            "placeholders": placeholders,
+        }))
+        .unwrap()
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn messages(errors: &[PlaceholderError]) -> Vec<String> {
+        errors
+            .iter()
+            .map(|error| format!("{}: {}", error.placeholder, error.message))
+            .collect()
+    }
+
+    #[test]
+    fn validates_declared_placeholders() {
+        let file: Command = serde_json::from_value(json!({
+            "name": "tar",
+            "commands": [{
+                "command": "tar -c{compression}f {archive} {files}",
+                "description": "Create an archive",
+                "placeholders": [
+                    {"name": "archive", "description": "Archive to create", "type": "path"},
+                    {"name": "archive", "description": "Archive to create"},
+                    {"name": "level", "description": "Compression level", "type": "integer"},
+                    {"name": "compression", "description": "Compression flag", "choices": []},
+                    {"name": "files", "description": "Files to add", "pattern": "(", "default": "."}
+                ]
+            }, {
+                "command": "tar -xf {archive} -C {dir}",
+                "description": "Extract an archive",
+                "placeholders": [
+                    {"name": "archive", "description": "Archive to extract", "pattern": ".*\\.tar", "default": "backup.tgz"},
+                    {"name": "dir", "description": "Directory to extract into", "type": "path"}
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let errors = validate(&file);
+        let messages = messages(&errors);
+        assert_eq!(messages.len(), 5, "{:?}", messages);
+        assert_eq!(messages[0], "archive: declared more than once");
+        assert_eq!(messages[1], "level: declared but not used in the command");
+        assert_eq!(messages[2], "compression: choices must not be empty");
+        assert!(messages[3].starts_with("files: invalid pattern: "));
+        assert!(messages[4].starts_with("archive: invalid default: "));
+        assert_eq!(errors[4].command, "tar -xf {archive} -C {dir}");
+        assert_eq!(messages.iter().filter(|m| m.contains("pattern")).count(), 1);
+    }
+
+    #[test]
+    fn resolves_values_defaults_and_optionals() {
+        let grep = command(
+            "grep {flags} -m {count} {pattern} {path}",
+            json!([
+                {"name": "flags", "description": "Extra flags", "optional": true},
+                {"name": "count", "description": "Most matches", "type": "integer", "default": "10"},
+                {"name": "pattern", "description": "Text to find"},
+                {"name": "path", "description": "File to search", "type": "path"}
+            ]),
+        );
+
+        let (resolved, errors) = resolve(&grep, &values(&[("pattern", "TODO"), ("path", "src")]));
+        assert!(errors.is_empty());
+        assert_eq!(
+            resolved,
+            values(&[
+                ("flags", ""),
+                ("count", "10"),
+                ("pattern", "TODO"),
+                ("path", "src")
+            ])
+        );
+
+        let (resolved, errors) = resolve(&grep, &values(&[("count", "many"), ("path", "src")]));
+        assert_eq!(
+            messages(&errors),
+            [
+                "count: expected an integer, got \"many\"",
+                "pattern: a value is required"
+            ]
+        );
+        assert_eq!(resolved, values(&[("flags", ""), ("path", "src")]));
+    }
+
+    #[test]
+    fn renders_without_the_space_of_omitted_placeholders() {
+        let ls = command(
+            "ls {flags} {path} {sort}",
+            json!([
+                {"name": "flags", "description": "Extra flags", "optional": true},
+                {"name": "path", "description": "Directory to list"},
+                {"name": "sort", "description": "Sort flag", "optional": true}
+            ]),
+        );
+        let awk = command(
+            "awk '{print $1,  $2}' {file}",
+            json!([{"name": "file", "description": "File to read"}]),
+        );
+
+        assert_eq!(
+            render(&ls, &values(&[("path", "/tmp")])).unwrap(),
+            "ls /tmp"
+        );
+        assert_eq!(
+            render(
+                &ls,
+                &values(&[("flags", "-la"), ("path", "/tmp"), ("sort", "-t")])
+            )
+            .unwrap(),
+            "ls -la /tmp -t"
+        );
+        assert_eq!(
+            render(&awk, &values(&[("file", "data.txt")])).unwrap(),
+            "awk '{print $1,  $2}' data.txt"
+        );
+        assert_eq!(
+            messages(&render(&ls, &HashMap::new()).unwrap_err()),
+            ["path: a value is required"]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::forge::{self, Update};
+use crate::registry::{Registry, DEFAULT_BRANCH};
+use anyhow::{anyhow, Result};
+use git2::{FetchOptions, Repository};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `[registry.poll]` of a registries file, for registries whose forge cannot reach the server.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PollConfig {
+    pub remote: String,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_branch() -> String {
+    DEFAULT_BRANCH.to_string()
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+impl PollConfig {
+    /// Polls the default branch of `remote` at the default interval.
+    pub fn new(remote: String) -> Self {
+        Self {
+            remote,
+            branch: default_branch(),
+            interval_secs: default_interval(),
+        }
+    }
+}
+
+/// Fetches the branch into a clone kept in the state directory and plans how to index its head
+/// from the `indexed` commit, returning the head with the plan.
+fn fetch(state_dir: &Path, config: &PollConfig, indexed: Option<&str>) -> Result<(String, Update)> {
+    let mirror = state_dir.join("mirror");
+    let repo = match Repository::open_bare(&mirror) {
+        Ok(repo) => repo,
+        Err(_) => Repository::init_bare(&mirror)?,
+    };
+
+    let mut remote = repo.remote_anonymous(&config.remote)?;
+    let tracking = format!("refs/remotes/origin/{}", config.branch);
+    let refspec = format!("+refs/heads/{}:{}", config.branch, tracking);
+    remote.fetch(&[&refspec], Some(&mut FetchOptions::new()), None)?;
+
+    let head = repo.refname_to_id(&tracking)?;
+    let update = match forge::plan(&repo, head, indexed, None)? {
+        // The branch was reset to an older commit, which is what the index should reflect.
+        Update::Behind => forge::plan(&repo, head, None, None)?,
+        update => update,
+    };
+
+    Ok((head.to_string(), update))
+}
+
+async fn poll_once(registry: &Registry, config: &PollConfig) -> Result<()> {
+    let transaction = registry.ledger.begin().await;
+    let indexed = transaction.ledger().commit.clone();
+
+    let (head, update) = tokio::task::spawn_blocking({
+        let state_dir = registry.state_dir();
+        let config = config.clone();
+        move || fetch(&state_dir, &config, indexed.as_deref())
+    })
+    .await
+    .map_err(|e| anyhow!("Fetching panicked: {}", e))??;
+    if matches!(update, Update::Unchanged) {
+        return transaction.commit_check();
+    }
+
+    let summary = registry
+        .apply_update(transaction, "poll", &head, update)
+        .await?;
+
+    tracing::info!(
+        "Indexed {} of {} for {}: {} commands added, {} commands removed",
+        head,
+        config.remote,
+        registry.name,
+        summary.added,
+        summary.removed
+    );
+    Ok(())
+}
+
+/// Fetches the registry's remote every interval and indexes what changed since the last
+/// indexed commit, keeping the index fresh without inbound webhooks.
+pub async fn poll(registry: Arc<Registry>, config: PollConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll_once(&registry, &config).await {
+            tracing::error!(
+                "Failed to poll {} for {}: {:#}",
+                config.remote,
+                registry.name,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,536 @@
+use crate::auth;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::forge::{Forge, Update};
+use crate::indexer::{self, IngestSummary};
+use crate::ledger::{LedgerStore, Transaction};
+use crate::open_ai;
+use crate::poller::PollConfig;
+use crate::template::EmbeddingTemplate;
+use crate::vector_db::VectorClient;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Name of the registry configured by the server's own settings and served on unprefixed routes.
+pub static DEFAULT_REGISTRY: &str = "default";
+
+/// Branch whose pushes are indexed unless a registry names another.
+pub static DEFAULT_BRANCH: &str = "main";
+
+#[derive(Deserialize, Debug)]
+struct RegistriesFile {
+    #[serde(default, rename = "registry")]
+    registries: Vec<RegistryConfig>,
+}
+
+/// A `[[registry]]` entry of the registries file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RegistryConfig {
+    pub name: String,
+    pub collection: String,
+    /// `owner/repo` whose pushes the registry accepts, any when unset.
+    pub repository: Option<String>,
+    /// Branch whose pushes are indexed, pushes to others are ignored.
+    pub branch: Option<String>,
+    /// Environment variable holding the secret webhooks are signed with, unless the secret is
+    /// set in `[webhook.registries]` of the server's configuration.
+    pub webhook_secret_env: Option<String>,
+    pub embedding_template: Option<String>,
+    pub embedding_model: Option<String>,
+    /// Local directory of registry files to index and watch instead of, or besides, webhooks.
+    pub directory: Option<PathBuf>,
+    pub poll: Option<PollConfig>,
+}
+
+/// A team's registry, its commands live in their own collection and are embedded with their
+/// own template and model.
+pub struct Registry {
+    pub name: String,
+    pub vector_client: VectorClient,
+    pub template: EmbeddingTemplate,
+    pub repository: Option<String>,
+    pub branch: String,
+    pub directory: Option<PathBuf>,
+    pub poll: Option<PollConfig>,
+    pub ledger: LedgerStore,
+    state_dir: PathBuf,
+    webhook_secret: Option<String>,
+}
+
+impl RegistryConfig {
+    /// The secret webhooks are signed with, from the server's configuration or the environment
+    /// variable named by `webhook_secret_env`.
+    fn webhook_secret(&self, settings: &Config) -> Result<Option<String>> {
+        let configured = settings.webhook.registries.get(&self.name);
+        match (configured, &self.webhook_secret_env) {
+            (Some(secret), _) => Ok(Some(secret.expose().to_string())),
+            (None, Some(name)) => env::var(name).map(Some).map_err(|_| {
+                anyhow!(
+                    "Registry {} reads its webhook secret from {}, which is not set",
+                    self.name,
+                    name
+                )
+            }),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl Registry {
+    fn new(
+        vector_client: &VectorClient,
+        settings: &Config,
+        config: RegistryConfig,
+    ) -> Result<Self> {
+        let webhook_secret = config.webhook_secret(settings)?;
+        let state_dir = settings.server.data_dir.join(&config.name);
+
+        Ok(Self {
+            vector_client: vector_client.for_collection(&config.collection),
+            template: EmbeddingTemplate::configured(
+                config.embedding_template.as_deref(),
+                config.embedding_model.as_deref(),
+            ),
+            repository: config.repository,
+            branch: config.branch.unwrap_or_else(|| DEFAULT_BRANCH.to_string()),
+            directory: config.directory,
+            poll: config.poll,
+            ledger: LedgerStore::open(&config.name, state_dir.join("ledger.json"))?,
+            state_dir,
+            webhook_secret,
+            name: config.name,
+        })
+    }
+
+    /// The default registry, configured by the server's own settings.
+    fn default_registry(vector_client: &VectorClient, settings: &Config) -> Result<Self> {
+        let state_dir = settings.server.data_dir.join(DEFAULT_REGISTRY);
+        Ok(Self {
+            name: DEFAULT_REGISTRY.to_string(),
+            vector_client: vector_client.for_collection(&settings.vector_store.collection),
+            template: EmbeddingTemplate::configured(
+                settings.embedding.template.as_deref(),
+                Some(&settings.embedding.model),
+            ),
+            repository: settings.default_registry.repository.clone(),
+            branch: settings.default_registry.branch.clone(),
+            directory: settings.default_registry.directory.clone(),
+            poll: settings.default_registry.poll.clone(),
+            ledger: LedgerStore::open(DEFAULT_REGISTRY, state_dir.join("ledger.json"))?,
+            state_dir,
+            webhook_secret: settings
+                .webhook
+                .secret
+                .as_ref()
+                .map(|secret| secret.expose().to_string()),
+        })
+    }
+
+    /// Creates the registry's collection unless it exists, sized by embedding a probe with the
+    /// registry's model.
+    pub async fn ensure_collection(&self) -> Result<()> {
+        if self.vector_client.exists().await? {
+            return Ok(());
+        }
+
+        let probe = open_ai::embed_query(self.template.model(), "spellbook").await?;
+        self.vector_client.create(probe.vec.len() as u64).await?;
+        tracing::info!(
+            "Created collection {} for registry {}",
+            self.vector_client.collection(),
+            self.name
+        );
+        Ok(())
+    }
+
+    /// Where state of the registry that outlives restarts is kept, under the data directory.
+    pub fn state_dir(&self) -> PathBuf {
+        self.state_dir.clone()
+    }
+
+    /// Applies a planned update of the registry's files, recording `head` as the indexed commit
+    /// once it is done.
+    pub async fn apply_update(
+        &self,
+        transaction: Transaction<'_>,
+        source: &str,
+        head: &str,
+        update: Update,
+    ) -> Result<IngestSummary> {
+        match update {
+            Update::Unchanged | Update::Behind => Ok(IngestSummary::default()),
+            Update::Changes { changes, .. } => {
+                let summary = indexer::apply_changes(
+                    &self.vector_client,
+                    &self.template,
+                    indexer::origin::REPOSITORY,
+                    &changes,
+                )
+                .await?;
+                transaction.commit_changes(source, Some(head), &changes, summary)?;
+                Ok(summary)
+            }
+            Update::Full(files) => {
+                let summary = indexer::sync(
+                    &self.vector_client,
+                    &self.template,
+                    indexer::origin::REPOSITORY,
+                    &files,
+                )
+                .await?;
+                transaction.commit_sync(source, Some(head), &files, summary)?;
+                Ok(summary)
+            }
+        }
+    }
+
+    /// Where pushes to the registry's repository are cloned.
+    pub fn clone_path(&self) -> PathBuf {
+        self.state_dir.join("repo")
+    }
+
+    /// Whether a push to `repository`, an `owner/repo` path, belongs to this registry.
+    pub fn tracks(&self, repository: &str) -> bool {
+        self.repository
+            .as_deref()
+            .is_none_or(|tracked| tracked.eq_ignore_ascii_case(repository))
+    }
+}
+
+/// Whether `name` can name a registry, which ends up in paths under the data directory and in
+/// `/r/:registry`.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// The registries listed in the registries file, failing on a name that is invalid or taken and
+/// on a collection another registry already indexes into.
+fn read_registries_file(settings: &Config, path: &std::path::Path) -> Result<Vec<RegistryConfig>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read registries file {}: {}", path.display(), e))?;
+    let file: RegistriesFile = toml::from_str(&content)
+        .map_err(|e| anyhow!("Invalid registries file {}: {}", path.display(), e))?;
+
+    let mut names = HashSet::from([DEFAULT_REGISTRY.to_string()]);
+    let mut collections = HashMap::from([(
+        settings.vector_store.collection.clone(),
+        DEFAULT_REGISTRY.to_string(),
+    )]);
+    for config in file.registries.iter() {
+        if !is_valid_name(&config.name) {
+            return Err(anyhow!(
+                "Registry name {:?} may only hold a-z, 0-9, _ and -",
+                config.name
+            ));
+        }
+        if !names.insert(config.name.clone()) {
+            return Err(anyhow!("Registry {} is configured twice", config.name));
+        }
+        if let Some(other) = collections.insert(config.collection.clone(), config.name.clone()) {
+            return Err(anyhow!(
+                "Registries {} and {} share the collection {}",
+                other,
+                config.name,
+                config.collection
+            ));
+        }
+    }
+    Ok(file.registries)
+}
+
+/// Every problem with the registries file that would stop the server from starting, such as a
+/// webhook secret whose environment variable is not set.
+pub fn check_registries_file(settings: &Config) -> Vec<String> {
+    let Some(path) = &settings.registries_file else {
+        return Vec::new();
+    };
+    match read_registries_file(settings, path) {
+        Ok(configs) => configs
+            .iter()
+            .filter_map(|config| config.webhook_secret(settings).err())
+            .map(|e| e.to_string())
+            .collect(),
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// Every registry served, by name.
+pub struct Registries {
+    registries: BTreeMap<String, Arc<Registry>>,
+}
+
+impl Registries {
+    /// The default registry plus those listed in the registries file.
+    pub fn new(vector_client: &VectorClient, settings: &Config) -> Result<Self> {
+        let mut registries = BTreeMap::new();
+        let default = Registry::default_registry(vector_client, settings)?;
+        registries.insert(default.name.clone(), Arc::new(default));
+
+        if let Some(path) = &settings.registries_file {
+            for config in read_registries_file(settings, path)? {
+                let registry = Registry::new(vector_client, settings, config)?;
+                registries.insert(registry.name.clone(), Arc::new(registry));
+            }
+        }
+
+        Ok(Self { registries })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Registry>> {
+        self.registries.get(name).cloned()
+    }
+
+    pub fn default_registry(&self) -> Arc<Registry> {
+        self.registries[DEFAULT_REGISTRY].clone()
+    }
+
+    pub fn all(&self) -> Vec<Arc<Registry>> {
+        self.registries.values().cloned().collect()
+    }
+}
+
+/// The registry a request is for, named by the `/r/:registry` prefix or the default registry on
+/// unprefixed routes.
+pub struct RegistryCtx(pub Arc<Registry>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RegistryCtx
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let registries = parts
+            .extensions
+            .get::<Arc<Registries>>()
+            .cloned()
+            .ok_or_else(|| ApiError::Internal(anyhow!("Registries are not configured")))?;
+
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+
+        match params.get("registry") {
+            None => Ok(RegistryCtx(registries.default_registry())),
+            Some(name) => registries
+                .get(name)
+                .map(RegistryCtx)
+                .ok_or_else(|| ApiError::NotFound(format!("No registry named {}", name))),
+        }
+    }
+}
+
+/// Whether a webhook may change the registry, it needs a valid signature, or token on GitLab, or
+/// an API key with the admin scope. The repository cloned comes from the payload, which only the
+/// forge vouches for when it is signed, so an unsigned push can resync the registry from any
+/// repository.
+pub fn authorize_webhook(
+    registry: &Registry,
+    forge: Forge,
+    key: Option<&auth::ApiKey>,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), ApiError> {
+    authorize(
+        registry.webhook_secret.as_deref(),
+        forge,
+        key,
+        headers,
+        body,
+    )
+}
+
+fn authorize(
+    webhook_secret: Option<&str>,
+    forge: Forge,
+    key: Option<&auth::ApiKey>,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), ApiError> {
+    if let Some(secret) = webhook_secret {
+        if forge.is_signed(headers) {
+            if forge.verify(secret, headers, body) {
+                return Ok(());
+            }
+            return Err(ApiError::Unauthorized(
+                "Invalid webhook signature".to_string(),
+            ));
+        }
+    }
+
+    match key {
+        Some(key) if key.allows(auth::Scope::Admin) => Ok(()),
+        Some(key) => Err(ApiError::Forbidden(format!(
+            "API key {} lacks the admin scope needed for unsigned webhooks",
+            key.id
+        ))),
+        None => Err(ApiError::Unauthorized(
+            "Webhooks need a valid signature or an API key with the admin scope".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{ApiKey, Scope};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    static BODY: &[u8] = br#"{"ref":"refs/heads/main"}"#;
+
+    fn key(scope: Scope) -> ApiKey {
+        ApiKey {
+            id: "0123456789ab".to_string(),
+            name: "webhook".to_string(),
+            hash: String::new(),
+            scopes: vec![scope],
+            user: None,
+            created_at: 0,
+        }
+    }
+
+    fn signed(secret: &str) -> HeaderMap {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(BODY);
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "push".parse().unwrap());
+        headers.insert(
+            "x-hub-signature-256",
+            format!("sha256={}", signature).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn accepts_signed_webhooks() {
+        let headers = signed("secret");
+        assert!(authorize(Some("secret"), Forge::GitHub, None, &headers, BODY).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_signatures_whatever_the_key() {
+        let headers = signed("other");
+        let admin = key(Scope::Admin);
+        assert!(matches!(
+            authorize(Some("secret"), Forge::GitHub, Some(&admin), &headers, BODY),
+            Err(ApiError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn unsigned_webhooks_need_an_admin_key() {
+        let headers = HeaderMap::new();
+        let write = key(Scope::Write);
+        let admin = key(Scope::Admin);
+
+        assert!(matches!(
+            authorize(Some("secret"), Forge::GitHub, None, &headers, BODY),
+            Err(ApiError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            authorize(Some("secret"), Forge::GitHub, Some(&write), &headers, BODY),
+            Err(ApiError::Forbidden(_))
+        ));
+        assert!(authorize(None, Forge::GitHub, Some(&admin), &headers, BODY).is_ok());
+    }
+
+    #[test]
+    fn registry_names_are_safe_in_paths_and_routes() {
+        for name in ["default", "platform-team", "ops_2"] {
+            assert!(is_valid_name(name), "{}", name);
+        }
+        for name in [
+            "", "..", "../etc", "team/ops", "Platform", "ops team", "ops%2F",
+        ] {
+            assert!(!is_valid_name(name), "{}", name);
+        }
+    }
+
+    fn check_file(name: &str, content: &str) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!(
+            "spellbook-registries-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, content).unwrap();
+        let settings = Config {
+            registries_file: Some(path.clone()),
+            ..Config::default()
+        };
+        let problems = check_registries_file(&settings);
+        fs::remove_file(&path).unwrap();
+        problems
+    }
+
+    #[test]
+    fn checks_the_registries_file() {
+        let valid = r#"
+            [[registry]]
+            name = "infra"
+            collection = "infra-commands"
+            repository = "acme/infra-spells"
+
+            [registry.poll]
+            remote = "https://github.com/acme/infra-spells.git"
+        "#;
+        assert!(check_file("valid", valid).is_empty());
+
+        let unset_secret = r#"
+            [[registry]]
+            name = "infra"
+            collection = "infra-commands"
+            webhook_secret_env = "SPELLBOOK_TEST_UNSET_WEBHOOK_SECRET"
+        "#;
+        assert_eq!(
+            check_file("unset-secret", unset_secret),
+            ["Registry infra reads its webhook secret from SPELLBOOK_TEST_UNSET_WEBHOOK_SECRET, which is not set"]
+        );
+
+        let shared = r#"
+            [[registry]]
+            name = "infra"
+            collection = "commands-v0"
+        "#;
+        assert_eq!(
+            check_file("shared", shared),
+            ["Registries default and infra share the collection commands-v0"]
+        );
+
+        let taken = r#"
+            [[registry]]
+            name = "default"
+            collection = "other-commands"
+        "#;
+        assert_eq!(
+            check_file("taken", taken),
+            ["Registry default is configured twice"]
+        );
+
+        let invalid = r#"
+            [[registry]]
+            name = "../infra"
+            collection = "infra-commands"
+        "#;
+        assert_eq!(
+            check_file("invalid", invalid),
+            ["Registry name \"../infra\" may only hold a-z, 0-9, _ and -"]
+        );
+    }
+}
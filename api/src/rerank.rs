@@ -0,0 +1,197 @@
+use crate::config::{RerankConfig, RerankerKind};
+use crate::open_ai;
+use crate::search::{ScoreStage, SearchResult};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+static TOKEN_BOOST: f32 = 0.03;
+static FLAG_BOOST: f32 = 0.1;
+static TOOL_BOOST: f32 = 0.1;
+
+/// Reorders the candidates returned by the vector search, best match first.
+///
+/// The scores a reranker leaves on the candidates only order them within this one query of one
+/// registry, they are not comparable across queries or registries.
+#[async_trait]
+pub trait Reranker: Send + Sync {
+    async fn rerank(&self, query: &str, candidates: Vec<SearchResult>)
+        -> Result<Vec<SearchResult>>;
+}
+
+/// The configured reranker, `None` when reranking is disabled.
+pub fn configured(config: &RerankConfig) -> Option<Arc<dyn Reranker>> {
+    match config.reranker? {
+        RerankerKind::Heuristic => Some(Arc::new(HeuristicReranker)),
+        RerankerKind::Llm => Some(Arc::new(LlmReranker {
+            model: config.model.clone(),
+        })),
+    }
+}
+
+fn tokens(text: &str) -> HashSet<String> {
+    text.split(|c: char| c.is_whitespace() || "\"'`,;:()[]=|".contains(c))
+        .map(|token| token.trim_end_matches(['.', '?', '!']))
+        .filter(|token| !token.is_empty() && !token.starts_with('{'))
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+/// Boosts candidates whose command contains the flags, words or tool named in the query.
+pub struct HeuristicReranker;
+
+#[async_trait]
+impl Reranker for HeuristicReranker {
+    async fn rerank(
+        &self,
+        query: &str,
+        candidates: Vec<SearchResult>,
+    ) -> Result<Vec<SearchResult>> {
+        let query_tokens = tokens(query);
+
+        let mut reranked: Vec<SearchResult> = candidates
+            .into_iter()
+            .map(|mut candidate| {
+                let command_tokens = tokens(&candidate.command.command);
+                let tool = candidate
+                    .command
+                    .tool
+                    .clone()
+                    .or(candidate
+                        .command
+                        .command
+                        .split_whitespace()
+                        .next()
+                        .map(String::from))
+                    .unwrap_or_default()
+                    .to_lowercase();
+
+                let mut boost = 0.0;
+                for token in query_tokens.iter() {
+                    if !command_tokens.contains(token) {
+                        continue;
+                    }
+                    boost += if token.starts_with('-') {
+                        FLAG_BOOST
+                    } else {
+                        TOKEN_BOOST
+                    };
+                }
+                if query_tokens.contains(&tool) {
+                    boost += TOOL_BOOST;
+                }
+
+                candidate.score += boost;
+                candidate.stage = ScoreStage::Heuristic;
+                candidate
+            })
+            .collect();
+
+        reranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(reranked)
+    }
+}
+
+/// Asks a chat model to order the candidates by relevance to the query.
+///
+/// The model only returns an order, so the scores are spread evenly over that order from 1 down
+/// and say nothing about how well the candidates match.
+pub struct LlmReranker {
+    model: String,
+}
+
+static LLM_RERANK_PROMPT: &str = "You rank cli commands by how well they answer a search query. \
+Reply with only a JSON array of the candidate numbers, most relevant first.";
+
+#[async_trait]
+impl Reranker for LlmReranker {
+    async fn rerank(
+        &self,
+        query: &str,
+        candidates: Vec<SearchResult>,
+    ) -> Result<Vec<SearchResult>> {
+        let listing = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| {
+                format!(
+                    "{}. {} : {}",
+                    i, candidate.command.command, candidate.command.description
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        let prompt = format!("Query: {}\n\nCandidates:\n{}", query, listing);
+
+        let reply = open_ai::complete(&self.model, LLM_RERANK_PROMPT, &prompt).await?;
+        let order: Vec<usize> = serde_json::from_str(reply.trim())
+            .map_err(|e| anyhow!("Unexpected reranking reply {:?}: {}", reply, e))?;
+
+        let mut candidates: Vec<Option<SearchResult>> = candidates.into_iter().map(Some).collect();
+        let mut ranked: Vec<SearchResult> = order
+            .into_iter()
+            .filter_map(|i| candidates.get_mut(i).and_then(Option::take))
+            .collect();
+        // Candidates the model left out keep their vector order behind the ranked ones.
+        ranked.extend(candidates.into_iter().flatten());
+
+        let count = ranked.len() as f32;
+        for (rank, candidate) in ranked.iter_mut().enumerate() {
+            candidate.score = 1.0 - rank as f32 / count;
+            candidate.stage = ScoreStage::Llm;
+        }
+
+        Ok(ranked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn candidate(command: &str, score: f32) -> SearchResult {
+        SearchResult {
+            command: serde_json::from_value(json!({
+                "command": command,
+                "description": "",
+            }))
+            .unwrap(),
+            score,
+            stage: ScoreStage::Vector,
+            owner: None,
+            registry: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn boosts_matching_tool_flags_and_words() {
+        let candidates = vec![
+            candidate("find . -name {pattern}", 0.80),
+            candidate("ls -la {dir}", 0.75),
+            candidate("ls {dir}", 0.78),
+        ];
+
+        let reranked = HeuristicReranker
+            .rerank("ls -la hidden files", candidates)
+            .await
+            .unwrap();
+
+        let order: Vec<&str> = reranked
+            .iter()
+            .map(|result| result.command.command.as_str())
+            .collect();
+        assert_eq!(
+            order,
+            ["ls -la {dir}", "ls {dir}", "find . -name {pattern}"]
+        );
+        // Tool, flag and word for the first, tool and word for the second.
+        assert!((reranked[0].score - 0.98).abs() < 1e-6);
+        assert!((reranked[1].score - 0.91).abs() < 1e-6);
+        assert!((reranked[2].score - 0.80).abs() < 1e-6);
+        assert!(reranked
+            .iter()
+            .all(|result| result.stage == ScoreStage::Heuristic));
+    }
+}
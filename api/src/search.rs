@@ -0,0 +1,227 @@
+use crate::command::{self, SubCommand};
+use crate::open_ai;
+use crate::registry::Registry;
+use crate::rerank::Reranker;
+use anyhow::{anyhow, Result};
+use qdrant_client::qdrant::{Condition, Filter, ScoredPoint};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+/// Each command is stored as several vectors, a search fetches this many points per wanted
+/// result so the limit can still be filled after keeping only the best vector of each command.
+static VECTORS_PER_RESULT: u64 = 4;
+
+/// Number of candidates handed to the reranker when one is configured.
+static RERANK_CANDIDATES: usize = 20;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreStage {
+    Vector,
+    Heuristic,
+    Llm,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub command: SubCommand,
+    /// Orders the results of one query of one registry, not comparable across registries.
+    pub score: f32,
+    pub stage: ScoreStage,
+    /// Set on the searching user's personal commands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Registry the command comes from when searching several.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+}
+
+impl TryFrom<ScoredPoint> for SearchResult {
+    type Error = anyhow::Error;
+    fn try_from(scored_point: ScoredPoint) -> Result<Self> {
+        let score = scored_point.score;
+        let owner = json!(scored_point.payload)
+            .get("owner")
+            .and_then(|owner| owner.as_str())
+            .map(String::from);
+        Ok(SearchResult {
+            command: command::from_payload(&scored_point.payload)?,
+            score,
+            stage: ScoreStage::Vector,
+            owner,
+            registry: None,
+        })
+    }
+}
+
+/// Public commands, plus the personal commands of `user` when searching as one.
+fn visible_to(user: Option<&str>) -> Filter {
+    let public = Condition::is_empty("owner");
+    match user {
+        Some(user) => Filter::should([public, Condition::matches("owner", user.to_string())]),
+        None => Filter::must([public]),
+    }
+}
+
+pub async fn search(
+    registry: &Registry,
+    reranker: Option<&dyn Reranker>,
+    query: &str,
+    limit: usize,
+    user: Option<&str>,
+) -> Result<Vec<SearchResult>> {
+    let candidates = match reranker {
+        Some(_) => RERANK_CANDIDATES.max(limit),
+        None => limit,
+    };
+
+    let embedded_query = open_ai::embed_query(registry.template.model(), query).await?;
+    let search_result = registry
+        .vector_client
+        .search(
+            embedded_query,
+            candidates as u64 * VECTORS_PER_RESULT,
+            Some(visible_to(user)),
+        )
+        .await?;
+
+    let mut seen = HashSet::new();
+    let mut results: Vec<SearchResult> = search_result
+        .result
+        .into_iter()
+        .filter_map(|result| result.try_into().ok())
+        .filter(|result: &SearchResult| seen.insert(result.command.command.clone()))
+        .take(candidates)
+        .collect();
+
+    if let Some(reranker) = reranker {
+        match reranker.rerank(query, results.clone()).await {
+            Ok(reranked) => results = reranked,
+            Err(e) => tracing::warn!("Reranking failed, keeping vector order: {:#}", e),
+        }
+    }
+
+    results.truncate(limit);
+    Ok(results)
+}
+
+/// Searches the registries concurrently and interleaves their results by rank, the best of each
+/// registry first, keeping `limit` results labelled with the registry they come from. Scores are
+/// only comparable within one registry, so they do not decide the order across registries. A
+/// registry that fails is logged and left out, the search only fails when every registry does.
+pub async fn search_all(
+    registries: &[Arc<Registry>],
+    reranker: Option<Arc<dyn Reranker>>,
+    query: &str,
+    limit: usize,
+    user: Option<&str>,
+) -> Result<Vec<SearchResult>> {
+    let mut searches = JoinSet::new();
+    for (i, registry) in registries.iter().enumerate() {
+        let registry = registry.clone();
+        let reranker = reranker.clone();
+        let query = query.to_string();
+        let user = user.map(String::from);
+        searches.spawn(async move {
+            let found = search(
+                &registry,
+                reranker.as_deref(),
+                &query,
+                limit,
+                user.as_deref(),
+            )
+            .await;
+            (i, registry, found)
+        });
+    }
+
+    let mut ranked = vec![Vec::new(); registries.len()];
+    let mut last_error = None;
+    let mut answered = false;
+    while let Some(joined) = searches.join_next().await {
+        let (i, registry, found) = joined.map_err(|e| anyhow!("Search panicked: {}", e))?;
+        match found {
+            Ok(found) => {
+                answered = true;
+                ranked[i] = found
+                    .into_iter()
+                    .map(|result| SearchResult {
+                        registry: Some(registry.name.clone()),
+                        ..result
+                    })
+                    .collect();
+            }
+            Err(e) => {
+                tracing::warn!("Leaving {} out of the search: {:#}", registry.name, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(e) = last_error.filter(|_| !answered) {
+        return Err(e);
+    }
+
+    Ok(interleave(
+        ranked.into_iter().map(Vec::into_iter).collect(),
+        limit,
+    ))
+}
+
+/// The first result of every list, then the second of every list and so on, up to `limit`.
+fn interleave<T>(mut lists: Vec<impl Iterator<Item = T>>, limit: usize) -> Vec<T> {
+    let mut interleaved = Vec::new();
+    while interleaved.len() < limit {
+        let before = interleaved.len();
+        for list in lists.iter_mut() {
+            if interleaved.len() == limit {
+                break;
+            }
+            interleaved.extend(list.next());
+        }
+        if interleaved.len() == before {
+            break;
+        }
+    }
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaves_results_by_rank() {
+        let lists = vec![
+            vec!["default 1", "default 2", "default 3"].into_iter(),
+            vec!["infra 1"].into_iter(),
+            vec!["ops 1", "ops 2"].into_iter(),
+        ];
+
+        assert_eq!(
+            interleave(lists, 10),
+            [
+                "default 1",
+                "infra 1",
+                "ops 1",
+                "default 2",
+                "ops 2",
+                "default 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn interleaving_stops_at_the_limit() {
+        let lists = vec![
+            vec!["default 1", "default 2"].into_iter(),
+            vec!["infra 1", "infra 2"].into_iter(),
+        ];
+
+        assert_eq!(interleave(lists, 3), ["default 1", "infra 1", "default 2"]);
+        assert!(interleave(Vec::<std::vec::IntoIter<&str>>::new(), 3).is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::command::SubCommand;
+use crate::indexer;
+use crate::template::EmbeddingTemplate;
+use crate::vector_db::{self, VectorClient};
+use anyhow::{anyhow, Result};
+use qdrant_client::qdrant::{vectors::VectorsOptions, Condition, Filter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+
+/// Bumped whenever the layout of `Snapshot` changes in a way older clients cannot read.
+pub static SNAPSHOT_VERSION: u32 = 1;
+
+/// Identifies the first line of a JSONL snapshot file.
+pub static SNAPSHOT_FORMAT: &str = "spellbook-snapshot";
+
+/// The whole indexed registry, so clients can search it without reaching the server.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub model: String,
+    pub template_version: String,
+    pub commands: Vec<SubCommand>,
+    /// Every stored point with its vector, only included when asked for.
+    pub points: Option<Vec<SnapshotPoint>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotPoint {
+    pub id: String,
+    pub payload: Value,
+    pub vector: Vec<f32>,
+}
+
+/// First line of a JSONL snapshot file, every following line is a `SnapshotPoint`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotHeader {
+    pub format: String,
+    pub version: u32,
+    pub model: String,
+    pub template_version: String,
+    pub dimensions: usize,
+    pub points: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+impl Snapshot {
+    /// The snapshot as a portable JSONL file, a header line followed by one line per point.
+    pub fn to_jsonl(&self) -> Result<String> {
+        let points = self.points.as_deref().unwrap_or_default();
+        let header = SnapshotHeader {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: self.version,
+            model: self.model.clone(),
+            template_version: self.template_version.clone(),
+            dimensions: points.first().map_or(0, |point| point.vector.len()),
+            points: points.len(),
+        };
+
+        let mut lines = vec![serde_json::to_string(&header)?];
+        for point in points.iter() {
+            lines.push(serde_json::to_string(point)?);
+        }
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+pub fn parse_jsonl(body: &str) -> Result<(SnapshotHeader, Vec<SnapshotPoint>)> {
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+    let header: SnapshotHeader = serde_json::from_str(lines.next().unwrap_or_default())
+        .map_err(|e| anyhow!("Invalid snapshot header: {}", e))?;
+
+    if header.format != SNAPSHOT_FORMAT || header.version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "Unsupported snapshot {} version {}, expected {} version {}",
+            header.format,
+            header.version,
+            SNAPSHOT_FORMAT,
+            SNAPSHOT_VERSION
+        ));
+    }
+
+    let points = lines
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow!("Invalid point on line {}: {}", i + 2, e))
+        })
+        .collect::<Result<Vec<SnapshotPoint>>>()?;
+
+    if points.len() != header.points {
+        return Err(anyhow!(
+            "Snapshot header lists {} points but the file holds {}",
+            header.points,
+            points.len()
+        ));
+    }
+
+    Ok((header, points))
+}
+
+/// Checks that a point holds a command and belongs to a known user, rebuilding its payload so
+/// that its ids match the command rather than trusting the file.
+fn validate_point(
+    point: SnapshotPoint,
+    template: &EmbeddingTemplate,
+    users: &HashSet<String>,
+) -> Result<SnapshotPoint> {
+    let command: SubCommand = serde_json::from_value(point.payload.clone())
+        .map_err(|e| anyhow!("Point {} does not hold a command: {}", point.id, e))?;
+    let field = |name: &str| point.payload.get(name).and_then(|value| value.as_str());
+
+    let owner = field("owner");
+    if let Some(owner) = owner.filter(|owner| !users.contains(*owner)) {
+        return Err(anyhow!(
+            "Point {} belongs to {}, who has no API key",
+            point.id,
+            owner
+        ));
+    }
+
+    // Points written before commands had several vectors carry no `vector`.
+    let vector = field("vector").unwrap_or("description");
+    if point.id != indexer::point_id(&command, owner, vector) {
+        return Err(anyhow!(
+            "Point {} does not match the id of its command",
+            point.id
+        ));
+    }
+
+    let mut payload = template.payload(&command, owner, field("origin"), vector)?;
+    payload["template_version"] = json!(field("template_version"));
+    Ok(SnapshotPoint { payload, ..point })
+}
+
+/// Loads a JSONL snapshot into the vector store. Points that already exist are skipped unless
+/// `overwrite` is set. Points with an outdated template version are re-embedded on the next start.
+/// Personal commands are only restored for `users` that still exist.
+pub async fn import(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    body: &str,
+    overwrite: bool,
+    users: &HashSet<String>,
+) -> Result<ImportSummary> {
+    let (header, points) = parse_jsonl(body)?;
+    let points = points
+        .into_iter()
+        .map(|point| validate_point(point, template, users))
+        .collect::<Result<Vec<_>>>()?;
+
+    if header.model != template.model() {
+        return Err(anyhow!(
+            "Snapshot was embedded with {}, this registry uses {}",
+            header.model,
+            template.model()
+        ));
+    }
+
+    let dimensions = vector_client.vector_size().await? as usize;
+    if let Some(point) = points.iter().find(|point| point.vector.len() != dimensions) {
+        return Err(anyhow!(
+            "Point {} has {} dimensions, the collection expects {}",
+            point.id,
+            point.vector.len(),
+            dimensions
+        ));
+    }
+
+    let existing: HashSet<String> = if overwrite {
+        HashSet::new()
+    } else {
+        vector_client
+            .scroll_all(false)
+            .await?
+            .into_iter()
+            .filter_map(|point| vector_db::point_id_to_string(point.id))
+            .collect()
+    };
+
+    let mut summary = ImportSummary::default();
+    for point in points.into_iter() {
+        if existing.contains(&point.id) {
+            summary.skipped += 1;
+            continue;
+        }
+        vector_client
+            .insert_vector(&point.id, point.vector, point.payload)
+            .await?;
+        summary.imported += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Snapshot of the registry, personal commands are only included in full backups.
+pub async fn build(
+    vector_client: &VectorClient,
+    template: &EmbeddingTemplate,
+    with_vectors: bool,
+    include_personal: bool,
+) -> Result<Snapshot> {
+    let public = (!include_personal).then(|| Filter::must([Condition::is_empty("owner")]));
+    let retrieved = vector_client.scroll(public, with_vectors).await?;
+
+    // Commands are stored once per vector, keep a single copy of each in a stable order.
+    let mut commands = BTreeMap::new();
+    let mut points = Vec::new();
+
+    for point in retrieved.into_iter() {
+        let payload = json!(point.payload);
+        let command: SubCommand = match serde_json::from_value(payload.clone()) {
+            Ok(command) => command,
+            Err(_) => continue,
+        };
+
+        if with_vectors {
+            let vector = match point.vectors.and_then(|vectors| vectors.vectors_options) {
+                Some(VectorsOptions::Vector(vector)) => vector.data,
+                _ => continue,
+            };
+            let Some(id) = vector_db::point_id_to_string(point.id) else {
+                continue;
+            };
+            points.push(SnapshotPoint {
+                id,
+                payload,
+                vector,
+            });
+        }
+
+        commands.insert(command.command.clone(), command);
+    }
+
+    Ok(Snapshot {
+        version: SNAPSHOT_VERSION,
+        model: template.model().to_string(),
+        template_version: template.version().to_string(),
+        commands: commands.into_values().collect(),
+        points: with_vectors.then_some(points),
+    })
+}
@@ -0,0 +1,258 @@
+use crate::command::{CommandId, SubCommand};
+use crate::open_ai::EMBEDDING_MODEL;
+use anyhow::Result;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Template used when none is configured, through `embedding.template` or `EMBEDDING_TEMPLATE`.
+///
+/// Each line is only kept if every field referenced on it has a value, so a
+/// command without tags or placeholders does not produce dangling labels.
+pub static DEFAULT_TEMPLATE: &str = "{tool}
+{command} : {description}
+tags: {tags}
+aliases: {aliases}
+placeholders: {placeholders}";
+
+/// Names of the vectors embedded for every command, folded into the template version so that
+/// changing them also triggers a reindex.
+static VECTOR_LAYOUT: &str = "description,syntax,question-*";
+
+static FIELDS: [&str; 7] = [
+    "tool",
+    "command",
+    "description",
+    "tags",
+    "aliases",
+    "placeholders",
+    "placeholder_names",
+];
+
+/// Renders the texts that get embedded for a `SubCommand` and names the model embedding them.
+///
+/// The version is derived from the template, vector layout and model, so changing any of them
+/// changes the version stored alongside every point and marks them for reindexing.
+pub struct EmbeddingTemplate {
+    template: String,
+    model: String,
+    version: String,
+}
+
+impl EmbeddingTemplate {
+    pub fn new(template: &str, model: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(VECTOR_LAYOUT);
+        hasher.update(template);
+        // Left out for the default model so versions from before models were configurable hold.
+        if model != EMBEDDING_MODEL {
+            hasher.update(model);
+        }
+        let version = format!("{:x}", hasher.finalize())[..12].to_string();
+
+        Self {
+            template: template.to_string(),
+            model: model.to_string(),
+            version,
+        }
+    }
+
+    /// A template where anything not configured takes its default. Escaped `\n`s in the
+    /// template become line breaks so it fits on one line of configuration.
+    pub fn configured(template: Option<&str>, model: Option<&str>) -> Self {
+        let template = template.map_or(DEFAULT_TEMPLATE.to_string(), |template| {
+            template.replace("\\n", "\n")
+        });
+        Self::new(&template, model.unwrap_or(EMBEDDING_MODEL))
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn render(&self, command: &SubCommand) -> String {
+        self.template
+            .lines()
+            .filter_map(|line| render_line(line, command))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Named texts to embed for a command: the rendered template as `description`, the raw
+    /// command as `syntax` and one `question-{n}` per natural language question.
+    pub fn vectors(&self, command: &SubCommand) -> Vec<(String, String)> {
+        let mut vectors = vec![
+            ("description".to_string(), self.render(command)),
+            ("syntax".to_string(), command.command.clone()),
+        ];
+
+        for (i, question) in command.questions.iter().flatten().enumerate() {
+            vectors.push((format!("question-{}", i), question.clone()));
+        }
+
+        vectors
+    }
+
+    /// Payload stored next to an embedding, the `SubCommand` tagged with the template version,
+    /// the id shared by all of its vectors, the name of the vector, the owner of personal
+    /// commands and the origin of public ones.
+    pub fn payload(
+        &self,
+        command: &SubCommand,
+        owner: Option<&str>,
+        origin: Option<&str>,
+        vector: &str,
+    ) -> Result<Value> {
+        let mut payload = serde_json::to_value(command)?;
+        payload["template_version"] = json!(self.version);
+        payload["command_id"] = json!(command.id(owner));
+        payload["vector"] = json!(vector);
+        if let Some(owner) = owner {
+            payload["owner"] = json!(owner);
+        }
+        if let Some(origin) = origin {
+            payload["origin"] = json!(origin);
+        }
+        Ok(payload)
+    }
+
+    pub fn is_stale(&self, payload: &Value) -> bool {
+        payload.get("template_version").and_then(|v| v.as_str()) != Some(self.version.as_str())
+    }
+}
+
+fn field_value(field: &str, command: &SubCommand) -> String {
+    let join = |values: &Option<Vec<String>>| values.as_deref().unwrap_or_default().join(", ");
+    let placeholders = command.placeholders.as_deref().unwrap_or_default();
+
+    match field {
+        "tool" => command.tool.clone().unwrap_or_default(),
+        "command" => command.command.clone(),
+        "description" => command.description.clone(),
+        "tags" => join(&command.tags),
+        "aliases" => join(&command.aliases),
+        "placeholders" => placeholders
+            .iter()
+            .map(|placeholder| format!("{} ({})", placeholder.name, placeholder.description))
+            .collect::<Vec<String>>()
+            .join(", "),
+        "placeholder_names" => placeholders
+            .iter()
+            .map(|placeholder| placeholder.name.clone())
+            .collect::<Vec<String>>()
+            .join(", "),
+        _ => String::new(),
+    }
+}
+
+fn render_line(line: &str, command: &SubCommand) -> Option<String> {
+    let mut rendered = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let field = &rest[start + 1..start + end];
+        rendered.push_str(&rest[..start]);
+
+        if FIELDS.contains(&field) {
+            let value = field_value(field, command);
+            if value.trim().is_empty() {
+                return None;
+            }
+            rendered.push_str(&value);
+        } else {
+            rendered.push_str(&rest[start..=start + end]);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    let rendered = rendered.trim();
+    if rendered.is_empty() {
+        return None;
+    }
+    Some(rendered.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> SubCommand {
+        serde_json::from_value(json!({
+            "tool": "git",
+            "command": "git log --author={author}",
+            "description": "Show the commits of an author",
+            "tags": ["history", "log"],
+            "aliases": ["commits by"],
+            "placeholders": [
+                {"name": "author", "description": "Name or email of the author"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn version_follows_template_and_model() {
+        let version = EmbeddingTemplate::new(DEFAULT_TEMPLATE, EMBEDDING_MODEL);
+
+        assert_eq!(
+            version.version(),
+            EmbeddingTemplate::new(DEFAULT_TEMPLATE, EMBEDDING_MODEL).version()
+        );
+        assert_eq!(
+            version.version(),
+            EmbeddingTemplate::configured(None, None).version()
+        );
+        assert_ne!(
+            version.version(),
+            EmbeddingTemplate::new("{command} : {description}", EMBEDDING_MODEL).version()
+        );
+        assert_ne!(
+            version.version(),
+            EmbeddingTemplate::new(DEFAULT_TEMPLATE, "text-embedding-3-large").version()
+        );
+    }
+
+    #[test]
+    fn renders_every_field() {
+        let command = command();
+        let line = FIELDS
+            .iter()
+            .map(|field| format!("{}=[{{{}}}]", field, field))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        assert_eq!(
+            render_line(&line, &command).unwrap(),
+            "tool=[git] command=[git log --author={author}] \
+             description=[Show the commits of an author] tags=[history, log] \
+             aliases=[commits by] placeholders=[author (Name or email of the author)] \
+             placeholder_names=[author]"
+        );
+    }
+
+    #[test]
+    fn drops_lines_with_an_empty_field() {
+        let mut command = command();
+        command.tags = None;
+
+        assert_eq!(render_line("tags: {tags}", &command), None);
+        assert_eq!(
+            render_line("{command} {unknown}", &command).unwrap(),
+            "git log --author={author} {unknown}"
+        );
+        assert_eq!(
+            EmbeddingTemplate::new(DEFAULT_TEMPLATE, EMBEDDING_MODEL).render(&command),
+            "git\n\
+             git log --author={author} : Show the commits of an author\n\
+             aliases: commits by\n\
+             placeholders: author (Name or email of the author)"
+        );
+    }
+}
@@ -7,3 +7,9 @@ pub fn uuid_hash(input: &str) -> String {
     let result = hasher.finalize();
     format!("{:?}", Uuid::new_v5(&Uuid::NAMESPACE_OID, &result[..16]))
 }
+
+pub fn sha256_hex(input: impl AsRef<[u8]>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(input);
+    format!("{:x}", hasher.finalize())
+}
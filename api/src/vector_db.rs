@@ -1,65 +1,252 @@
-use anyhow::Result;
+use crate::error::Upstream;
+use crate::metrics;
+use anyhow::{anyhow, Context, Result};
 use openai::embeddings::Embedding;
-use qdrant_client::prelude::QdrantClient;
+use qdrant_client::prelude::{Payload, QdrantClient};
 use qdrant_client::qdrant::{
-    points_selector::PointsSelectorOneOf, with_payload_selector::SelectorOptions, PointStruct,
-    PointsIdsList, PointsSelector, SearchPoints, SearchResponse, WithPayloadSelector,
+    point_id::PointIdOptions, points_selector::PointsSelectorOneOf, vectors_config,
+    with_payload_selector::SelectorOptions, with_vectors_selector, Condition, CreateCollection,
+    Distance, Filter, PointId, PointStruct, PointsIdsList, PointsSelector, RetrievedPoint,
+    ScrollPoints, SearchPoints, SearchResponse, VectorParams, VectorsConfig, WithPayloadSelector,
+    WithVectorsSelector,
 };
 use serde_json::Value;
+use std::sync::Arc;
 
-static COLLECTION_NAME: &str = "commands-v0";
+/// Collection of the default registry.
+pub static DEFAULT_COLLECTION: &str = "commands-v0";
 
+pub fn point_id_to_string(id: Option<PointId>) -> Option<String> {
+    match id?.point_id_options? {
+        PointIdOptions::Num(num) => Some(num.to_string()),
+        PointIdOptions::Uuid(uuid) => Some(uuid),
+    }
+}
+
+/// A connection to Qdrant scoped to one registry's collection.
 pub struct VectorClient {
-    client: QdrantClient,
+    client: Arc<QdrantClient>,
+    collection: String,
 }
 
 impl VectorClient {
     pub async fn new(url: &str, token: &str) -> Result<Self> {
         let client = QdrantClient::from_url(url).with_api_key(token).build()?;
-        Ok(Self { client })
+        Ok(Self {
+            client: Arc::new(client),
+            collection: DEFAULT_COLLECTION.to_string(),
+        })
+    }
+
+    /// A client for another collection sharing this client's connection.
+    pub fn for_collection(&self, collection: &str) -> Self {
+        Self {
+            client: self.client.clone(),
+            collection: collection.to_string(),
+        }
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    pub async fn exists(&self) -> Result<bool> {
+        metrics::vector_store(
+            "has_collection",
+            self.client.has_collection(&self.collection),
+        )
+        .await
+        .context(Upstream::VectorStore)
+    }
+
+    /// Creates the collection for vectors of `size` dimensions.
+    pub async fn create(&self, size: u64) -> Result<()> {
+        let collection = CreateCollection {
+            collection_name: self.collection.clone(),
+            vectors_config: Some(VectorsConfig {
+                config: Some(vectors_config::Config::Params(VectorParams {
+                    size,
+                    distance: Distance::Cosine.into(),
+                    ..Default::default()
+                })),
+            }),
+            ..Default::default()
+        };
+        metrics::vector_store(
+            "create_collection",
+            self.client.create_collection(&collection),
+        )
+        .await
+        .context(Upstream::VectorStore)?;
+
+        Ok(())
     }
 
     pub async fn insert(&self, id: &str, embedding: Embedding, payload: Value) -> Result<()> {
         let vec: Vec<f32> = embedding.vec.iter().map(|&x| x as f32).collect();
-        let payload = payload.try_into().unwrap();
+        self.insert_vector(id, vec, payload).await
+    }
+
+    pub async fn insert_vector(&self, id: &str, vec: Vec<f32>, payload: Value) -> Result<()> {
+        let payload: Payload = payload
+            .try_into()
+            .map_err(|e| anyhow!("Invalid payload for point {}: {}", id, e))?;
         let points = vec![PointStruct::new(id.to_string(), vec, payload)];
-        self.client
-            .upsert_points(COLLECTION_NAME, None, points, None)
-            .await?;
+        metrics::vector_store(
+            "upsert_points",
+            self.client
+                .upsert_points(&self.collection, None, points, None),
+        )
+        .await
+        .context(Upstream::VectorStore)?;
 
         Ok(())
     }
 
     pub async fn delete(&self, id: &str) -> Result<()> {
         let point_selector_one_of = PointsSelectorOneOf::Points(PointsIdsList {
-            ids: vec![id.to_string().try_into().unwrap()],
+            ids: vec![PointId::from(id.to_string())],
         });
         let points_selector = PointsSelector {
             points_selector_one_of: Some(point_selector_one_of),
         };
-        self.client
-            .delete_points(COLLECTION_NAME, None, &points_selector, None)
-            .await?;
+        metrics::vector_store(
+            "delete_points",
+            self.client
+                .delete_points(&self.collection, None, &points_selector, None),
+        )
+        .await
+        .context(Upstream::VectorStore)?;
 
         Ok(())
     }
 
-    pub async fn search(&self, query: Embedding, limit: u64) -> Result<SearchResponse> {
+    /// Number of points stored in the collection.
+    pub async fn point_count(&self) -> Result<u64> {
+        let info = metrics::vector_store(
+            "collection_info",
+            self.client.collection_info(&self.collection),
+        )
+        .await
+        .context(Upstream::VectorStore)?;
+
+        Ok(info
+            .result
+            .and_then(|info| info.points_count)
+            .unwrap_or_default())
+    }
+
+    /// Size of the vectors the collection was created for.
+    pub async fn vector_size(&self) -> Result<u64> {
+        let info = metrics::vector_store(
+            "collection_info",
+            self.client.collection_info(&self.collection),
+        )
+        .await
+        .context(Upstream::VectorStore)?;
+        let config = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors_config| vectors_config.config);
+
+        match config {
+            Some(vectors_config::Config::Params(params)) => Ok(params.size),
+            _ => Err(anyhow!(
+                "Collection {} has no single vector configuration",
+                self.collection
+            )),
+        }
+    }
+
+    /// Deletes every vector stored for the command with the given id.
+    pub async fn delete_command(&self, command_id: &str) -> Result<()> {
+        let filter = Filter::must([Condition::matches("command_id", command_id.to_string())]);
+        let points_selector = PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Filter(filter)),
+        };
+        metrics::vector_store(
+            "delete_points",
+            self.client
+                .delete_points(&self.collection, None, &points_selector, None),
+        )
+        .await
+        .context(Upstream::VectorStore)?;
+
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        query: Embedding,
+        limit: u64,
+        filter: Option<Filter>,
+    ) -> Result<SearchResponse> {
         let vec: Vec<f32> = query.vec.iter().map(|&x| x as f32).collect();
         let payload_selector = WithPayloadSelector {
             selector_options: Some(SelectorOptions::Enable(true)),
         };
 
         let search_points = SearchPoints {
-            collection_name: COLLECTION_NAME.to_string(),
+            collection_name: self.collection.clone(),
             vector: vec,
             limit,
+            filter,
             with_payload: Some(payload_selector),
             ..Default::default()
         };
 
-        let search_result = self.client.search_points(&search_points).await.unwrap();
+        let search_result =
+            metrics::vector_store("search_points", self.client.search_points(&search_points))
+                .await
+                .context(Upstream::VectorStore)?;
 
         Ok(search_result)
     }
+
+    /// Every point in the collection with its payload, and vector when `with_vectors` is set.
+    pub async fn scroll_all(&self, with_vectors: bool) -> Result<Vec<RetrievedPoint>> {
+        self.scroll(None, with_vectors).await
+    }
+
+    /// Every point matching the filter with its payload, and vector when `with_vectors` is set.
+    pub async fn scroll(
+        &self,
+        filter: Option<Filter>,
+        with_vectors: bool,
+    ) -> Result<Vec<RetrievedPoint>> {
+        let mut points = Vec::new();
+        let mut offset = None;
+
+        loop {
+            let scroll_points = ScrollPoints {
+                collection_name: self.collection.clone(),
+                filter: filter.clone(),
+                offset,
+                limit: Some(256),
+                with_payload: Some(WithPayloadSelector {
+                    selector_options: Some(SelectorOptions::Enable(true)),
+                }),
+                with_vectors: Some(WithVectorsSelector {
+                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(
+                        with_vectors,
+                    )),
+                }),
+                ..Default::default()
+            };
+
+            let response = metrics::vector_store("scroll", self.client.scroll(&scroll_points))
+                .await
+                .context(Upstream::VectorStore)?;
+            points.extend(response.result);
+
+            match response.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(points)
+    }
 }
@@ -0,0 +1,174 @@
+use crate::client::{Client, SearchResult};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use spellbook_command::SubCommand;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Snapshot format this client understands, see `/export` on the server.
+static SNAPSHOT_VERSION: u32 = 1;
+
+/// A cached registry older than this is refreshed after answering a search.
+static MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub commands: Vec<SubCommand>,
+}
+
+pub enum SyncOutcome {
+    Updated(usize),
+    Unchanged,
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    let dir = dirs::cache_dir()
+        .ok_or(anyhow!("Could not find a cache directory"))?
+        .join("spellbook");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn snapshot_path() -> Result<PathBuf> {
+    Ok(cache_dir()?.join("registry.json"))
+}
+
+fn etag_path() -> Result<PathBuf> {
+    Ok(cache_dir()?.join("registry.etag"))
+}
+
+/// Downloads the registry snapshot unless the cached copy is still current.
+pub fn sync(client: &Client) -> Result<SyncOutcome> {
+    let path = snapshot_path()?;
+    // An etag left without its snapshot must not stop the snapshot from being downloaded again.
+    let etag = if path.exists() {
+        fs::read_to_string(etag_path()?).ok()
+    } else {
+        None
+    };
+    let Some((body, etag)) = client.export(etag.as_deref())? else {
+        // Touch the snapshot so it counts as fresh again.
+        fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now())?;
+        return Ok(SyncOutcome::Unchanged);
+    };
+
+    let snapshot: Snapshot = serde_json::from_slice(&body)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "Server sent registry snapshot version {}, this client reads version {}",
+            snapshot.version,
+            SNAPSHOT_VERSION
+        ));
+    }
+
+    fs::write(&path, &body)?;
+    match etag {
+        Some(etag) => fs::write(etag_path()?, etag)?,
+        None => {
+            let _ = fs::remove_file(etag_path()?);
+        }
+    }
+
+    Ok(SyncOutcome::Updated(snapshot.commands.len()))
+}
+
+/// Whether `error` means the server cannot answer right now, because it is unreachable, timed
+/// out or failed with a 5xx. The cached registry stands in for it then.
+pub fn is_unavailable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<reqwest::Error>().is_some_and(|e| {
+        e.is_connect() || e.is_timeout() || e.status().is_some_and(|s| s.is_server_error())
+    })
+}
+
+/// Refreshes the cached registry once it is stale. A failed refresh only warns, the cached copy
+/// stays usable until the next one.
+pub fn refresh_if_stale(client: &Client) {
+    if !is_stale() {
+        return;
+    }
+    if let Err(e) = sync(client) {
+        eprintln!("Could not refresh the cached registry: {:#}", e);
+    }
+}
+
+pub fn is_stale() -> bool {
+    let modified = snapshot_path()
+        .and_then(|path| Ok(fs::metadata(path)?.modified()?))
+        .ok();
+
+    match modified.and_then(|modified| SystemTime::now().duration_since(modified).ok()) {
+        Some(age) => age > MAX_AGE,
+        None => true,
+    }
+}
+
+pub fn load() -> Result<Snapshot> {
+    let body = fs::read(snapshot_path()?)
+        .map_err(|_| anyhow!("No cached registry, run `spellbook sync` while online first"))?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+pub fn tokens(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+pub fn document(command: &SubCommand) -> HashSet<String> {
+    let mut text = format!("{} {}", command.command, command.description);
+    for extra in [&command.tags, &command.aliases, &command.questions] {
+        text.push(' ');
+        text.push_str(&extra.as_deref().unwrap_or_default().join(" "));
+    }
+    if let Some(tool) = &command.tool {
+        text.push(' ');
+        text.push_str(tool);
+    }
+    tokens(&text).into_iter().collect()
+}
+
+/// Lexical search over the cached registry, each query term weighted by how rare it is.
+pub fn search(snapshot: &Snapshot, query: &str, limit: usize) -> Vec<SearchResult> {
+    let documents: Vec<HashSet<String>> = snapshot.commands.iter().map(document).collect();
+    let query_tokens: HashSet<String> = tokens(query).into_iter().collect();
+
+    let mut frequency: HashMap<&str, usize> = HashMap::new();
+    for document in documents.iter() {
+        for token in query_tokens
+            .iter()
+            .filter(|token| document.contains(*token))
+        {
+            *frequency.entry(token.as_str()).or_default() += 1;
+        }
+    }
+
+    let count = documents.len() as f32;
+    let mut results: Vec<SearchResult> = snapshot
+        .commands
+        .iter()
+        .zip(documents.iter())
+        .filter_map(|(command, document)| {
+            let score: f32 = query_tokens
+                .iter()
+                .filter(|token| document.contains(*token))
+                .map(|token| (count / frequency[token.as_str()] as f32).ln() + 1.0)
+                .sum();
+            (score > 0.0).then(|| SearchResult {
+                command: command.clone(),
+                score: Some(score),
+                stage: Some("lexical".to_string()),
+            })
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
+    results.truncate(limit);
+    results
+}
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client as HttpClient, Response};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use spellbook_command::{Command, SubCommand};
+use std::time::Duration;
+
+static INGEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub command: SubCommand,
+    pub score: Option<f32>,
+    pub stage: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IngestSummary {
+    pub added: usize,
+    pub failed: usize,
+}
+
+/// Talks to a spellbook server's HTTP API.
+pub struct Client {
+    server: String,
+    http: HttpClient,
+}
+
+impl Client {
+    /// A client for `server`, authenticating every request with `token` when one is given.
+    pub fn new(server: &str, token: Option<&str>) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        Ok(Self {
+            server: server.trim_end_matches('/').to_string(),
+            http: HttpClient::builder().default_headers(headers).build()?,
+        })
+    }
+
+    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        let results = self
+            .http
+            .get(format!("{}/search", self.server))
+            .query(&[("query", query)])
+            .send()
+            .with_context(|| format!("Failed to reach spellbook server at {}", self.server))?
+            .error_for_status()?
+            .json()?;
+
+        Ok(results)
+    }
+
+    /// Fetches the registry snapshot, `None` when it still matches `etag`.
+    pub fn export(&self, etag: Option<&str>) -> Result<Option<(Vec<u8>, Option<String>)>> {
+        let mut request = self.http.get(format!("{}/export", self.server));
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = request
+            .send()
+            .with_context(|| format!("Failed to reach spellbook server at {}", self.server))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let response = response.error_for_status()?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from);
+
+        Ok(Some((response.bytes()?.to_vec(), etag)))
+    }
+
+    /// Downloads every point with its vector as a JSONL snapshot file.
+    pub fn export_snapshot(&self) -> Result<Vec<u8>> {
+        let response = self
+            .http
+            .get(format!("{}/export", self.server))
+            .query(&[("format", "jsonl")])
+            .send()
+            .with_context(|| format!("Failed to reach spellbook server at {}", self.server))?;
+
+        Ok(error_for_status(response)?.bytes()?.to_vec())
+    }
+
+    pub fn import_snapshot(&self, snapshot: Vec<u8>, overwrite: bool) -> Result<ImportSummary> {
+        let response = self
+            .http
+            .post(format!("{}/import", self.server))
+            .query(&[("overwrite", overwrite)])
+            .body(snapshot)
+            .send()
+            .with_context(|| format!("Failed to reach spellbook server at {}", self.server))?;
+
+        Ok(error_for_status(response)?.json()?)
+    }
+
+    /// Indexes registry files without going through the registry repository.
+    pub fn ingest(&self, commands: &[Command]) -> Result<IngestSummary> {
+        let response = self
+            .http
+            .post(format!("{}/ingest", self.server))
+            .json(commands)
+            // Every command is embedded before the server responds.
+            .timeout(INGEST_TIMEOUT)
+            .send()
+            .with_context(|| format!("Failed to reach spellbook server at {}", self.server))?;
+
+        Ok(error_for_status(response)?.json()?)
+    }
+}
+
+/// Like `Response::error_for_status`, but keeps the server's explanation of what went wrong.
+fn error_for_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().unwrap_or_default();
+    match serde_json::from_str::<Problem>(&body) {
+        Ok(problem) => Err(anyhow!(
+            "Server responded with {}: {} (request id {})",
+            status,
+            problem.detail,
+            problem.request_id.unwrap_or_default()
+        )),
+        Err(_) => Err(anyhow!("Server responded with {}: {}", status, body)),
+    }
+}
+
+/// The problem document the server describes errors with.
+#[derive(Deserialize)]
+struct Problem {
+    detail: String,
+    request_id: Option<String>,
+}
@@ -0,0 +1,503 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+mod cache;
+mod client;
+mod history;
+mod navi;
+mod private;
+mod prompt;
+mod tldr;
+
+use client::{Client, SearchResult};
+use history::HistoryFormat;
+use spellbook_command::{Command, SubCommand};
+
+#[derive(Parser)]
+#[command(
+    name = "spellbook",
+    about = "Search the spellbook registry from the terminal"
+)]
+struct Cli {
+    /// Spellbook server to talk to
+    #[arg(
+        long,
+        global = true,
+        env = "SPELLBOOK_URL",
+        default_value = "https://spellbook.fly.dev"
+    )]
+    server: String,
+
+    /// API key to authenticate with, searches include your personal commands when it has a user
+    #[arg(long, global = true, env = "SPELLBOOK_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Search for a command, pick a result and fill in its placeholders
+    Search {
+        query: Vec<String>,
+        /// Print the search results as JSON instead of prompting
+        #[arg(long)]
+        json: bool,
+        /// Take the top result without prompting for a selection
+        #[arg(long)]
+        first: bool,
+        /// Copy the final command to the clipboard instead of printing it
+        #[arg(long)]
+        copy: bool,
+        /// Search the cached registry without contacting the server
+        #[arg(long)]
+        offline: bool,
+        /// Offer the output of placeholders' `suggest` commands, asking before running each one
+        #[arg(long)]
+        run_suggestions: bool,
+    },
+    /// Download the registry for offline search, if it changed since the last sync
+    Sync,
+    /// Save every indexed point with its vector to a JSONL snapshot file
+    Export {
+        /// File to write, stdout when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Load a snapshot file written by `export` into the server's vector store
+    Import {
+        file: PathBuf,
+        /// Replace points that already exist instead of skipping them
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Save commands from your shell history as private commands that show up in searches
+    History {
+        /// History format to read, every shell with a history file when omitted
+        #[arg(long, value_enum)]
+        shell: Option<HistoryFormat>,
+        /// History file to read instead of the shell's default
+        #[arg(long, requires = "shell")]
+        file: Option<PathBuf>,
+        /// Number of the most frequently run commands to go through
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Save commands without asking for descriptions, using the last run as description
+        #[arg(long)]
+        no_annotate: bool,
+    },
+    /// Convert a local tldr-pages checkout into registry files
+    Tldr {
+        /// The tldr-pages checkout, or its pages directory
+        path: PathBuf,
+        /// Platform directories to convert, all of them when omitted
+        #[arg(long)]
+        platform: Vec<String>,
+        /// Directory to write registry files to, one subdirectory per platform
+        #[arg(long, short, required_unless_present = "ingest")]
+        output: Option<PathBuf>,
+        /// Send the converted commands to the server to be indexed
+        #[arg(long)]
+        ingest: bool,
+    },
+    /// Convert between navi cheat files and the registry
+    Navi {
+        #[command(subcommand)]
+        action: NaviAction,
+    },
+    /// Print a script binding Ctrl-G to a search that replaces the current command line
+    Init { shell: Shell },
+}
+
+#[derive(Subcommand)]
+enum NaviAction {
+    /// Convert `.cheat` files into registry files
+    Import {
+        files: Vec<PathBuf>,
+        /// Directory to write registry files to
+        #[arg(long, short, required_unless_present = "ingest")]
+        output: Option<PathBuf>,
+        /// Send the converted commands to the server to be indexed
+        #[arg(long)]
+        ingest: bool,
+    },
+    /// Write the whole registry as a navi cheat file
+    Export {
+        /// File to write, stdout when omitted
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Export the cached registry without syncing it first
+        #[arg(long)]
+        offline: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    fn script(&self) -> &'static str {
+        match self {
+            Shell::Bash => include_str!("shell/spellbook.bash"),
+            Shell::Zsh => include_str!("shell/spellbook.zsh"),
+            Shell::Fish => include_str!("shell/spellbook.fish"),
+        }
+    }
+}
+
+/// Number of results the server returns, matched when searching offline.
+static SEARCH_LIMIT: usize = 5;
+
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    let mut clipboard = arboard::Clipboard::new()?;
+    clipboard.set_text(text)?;
+    Ok(())
+}
+
+fn offline_search(query: &str) -> Result<Vec<SearchResult>> {
+    Ok(cache::search(&cache::load()?, query, SEARCH_LIMIT))
+}
+
+fn sync(client: &Client) -> Result<()> {
+    match cache::sync(client)? {
+        cache::SyncOutcome::Updated(count) => eprintln!("Cached {} commands", count),
+        cache::SyncOutcome::Unchanged => eprintln!("Cached registry is up to date"),
+    }
+    Ok(())
+}
+
+fn export(client: &Client, output: Option<PathBuf>) -> Result<()> {
+    let snapshot = client.export_snapshot()?;
+    match output {
+        Some(output) => {
+            fs::write(&output, snapshot)?;
+            eprintln!("Exported snapshot to {}", output.display());
+        }
+        None => std::io::stdout().write_all(&snapshot)?,
+    }
+    Ok(())
+}
+
+fn import(client: &Client, file: PathBuf, overwrite: bool) -> Result<()> {
+    let summary = client.import_snapshot(fs::read(file)?, overwrite)?;
+    eprintln!(
+        "Imported {} points, skipped {} that already existed",
+        summary.imported, summary.skipped
+    );
+    Ok(())
+}
+
+/// Commands sent to `/ingest` per request.
+static INGEST_BATCH: usize = 25;
+
+/// Writes one registry file per command into `dir`.
+fn write_registry(dir: &Path, commands: &[Command]) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for command in commands.iter() {
+        let file = dir.join(format!("{}.json", command.name.replace(' ', "-")));
+        fs::write(file, serde_json::to_string_pretty(command)?)?;
+    }
+    Ok(())
+}
+
+/// Sends commands to the server to be indexed, returning how many were added and failed.
+fn ingest(client: &Client, commands: &[Command]) -> Result<(usize, usize)> {
+    let (mut added, mut failed) = (0, 0);
+    for batch in commands.chunks(INGEST_BATCH) {
+        let summary = client.ingest(batch)?;
+        added += summary.added;
+        failed += summary.failed;
+    }
+    Ok((added, failed))
+}
+
+fn tldr(
+    client: &Client,
+    path: PathBuf,
+    platforms: Vec<String>,
+    output: Option<PathBuf>,
+    ingest_commands: bool,
+) -> Result<()> {
+    for platform in tldr::platforms(&path, &platforms)? {
+        let commands = tldr::convert_platform(&path, &platform)?;
+        eprintln!("Converted {} {} pages", commands.len(), platform);
+
+        if let Some(output) = &output {
+            write_registry(&output.join(&platform), &commands)?;
+        }
+
+        if ingest_commands {
+            let (added, failed) = ingest(client, &commands)?;
+            eprintln!("Indexed {} {} commands, {} failed", added, platform, failed);
+        }
+    }
+
+    Ok(())
+}
+
+fn navi_import(
+    client: &Client,
+    files: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    ingest_commands: bool,
+) -> Result<()> {
+    let mut commands = Vec::new();
+    for file in files {
+        commands.extend(navi::parse(&fs::read_to_string(file)?));
+    }
+    eprintln!("Converted {} cheat sections", commands.len());
+
+    if let Some(output) = &output {
+        write_registry(output, &commands)?;
+    }
+
+    if ingest_commands {
+        let (added, failed) = ingest(client, &commands)?;
+        eprintln!("Indexed {} commands, {} failed", added, failed);
+    }
+
+    Ok(())
+}
+
+fn navi_export(client: &Client, output: Option<PathBuf>, offline: bool) -> Result<()> {
+    if !offline {
+        if let Err(e) = cache::sync(client) {
+            if !cache::is_unavailable(&e) {
+                return Err(e);
+            }
+            eprintln!("Server unavailable, exporting the cached registry: {:#}", e);
+        }
+    }
+    let cheat = navi::render(&cache::load()?.commands);
+
+    match output {
+        Some(output) => {
+            fs::write(&output, cheat)?;
+            eprintln!("Exported navi cheat to {}", output.display());
+        }
+        None => print!("{}", cheat),
+    }
+    Ok(())
+}
+
+fn history(
+    shell: Option<HistoryFormat>,
+    file: Option<PathBuf>,
+    limit: usize,
+    no_annotate: bool,
+) -> Result<()> {
+    let sources: Vec<(HistoryFormat, PathBuf)> = match (shell, file) {
+        (Some(shell), Some(file)) => vec![(shell, file)],
+        (Some(shell), None) => shell
+            .default_path()
+            .map(|path| (shell, path))
+            .into_iter()
+            .collect(),
+        (None, _) => HistoryFormat::value_variants()
+            .iter()
+            .filter_map(|shell| shell.default_path().map(|path| (*shell, path)))
+            .filter(|(_, path)| path.exists())
+            .collect(),
+    };
+
+    let mut commands = Vec::new();
+    for (shell, path) in sources {
+        commands.extend(shell.read(&path)?);
+    }
+
+    let known: Vec<String> = private::load()?
+        .into_iter()
+        .map(|command| command.command)
+        .collect();
+    let candidates: Vec<history::Candidate> = history::candidates(&commands)
+        .into_iter()
+        .filter(|candidate| !known.contains(&candidate.command.command))
+        .take(limit)
+        .collect();
+    if candidates.is_empty() {
+        eprintln!("No new commands in your shell history");
+        return Ok(());
+    }
+
+    // Values seen in the history only become defaults once the user has reviewed them.
+    let annotate = !no_annotate && std::io::stderr().is_terminal();
+    let mut to_save = Vec::new();
+    for candidate in candidates {
+        let (description, keep_values) = if annotate {
+            match prompt::describe(&candidate)? {
+                Some(description) => (description, prompt::keep_values(&candidate)?),
+                None => continue,
+            }
+        } else {
+            (candidate.command.command.clone(), false)
+        };
+
+        let command = if keep_values {
+            candidate.with_defaults()
+        } else {
+            candidate.command
+        };
+        to_save.push(SubCommand {
+            description,
+            ..command
+        });
+    }
+
+    let saved = private::save(to_save)?;
+    eprintln!("Saved {} private commands", saved);
+    Ok(())
+}
+
+fn search(
+    client: &Client,
+    query: &str,
+    json: bool,
+    first: bool,
+    copy: bool,
+    offline: bool,
+    run_suggestions: bool,
+) -> Result<()> {
+    let interactive = std::io::stderr().is_terminal();
+    let query = match query.trim() {
+        "" if interactive => prompt::query()?,
+        query => query.to_string(),
+    };
+    let query = query.as_str();
+
+    if offline {
+        return answer(
+            query,
+            offline_search(query)?,
+            json,
+            first,
+            copy,
+            run_suggestions,
+        );
+    }
+
+    match client.search(query) {
+        Ok(results) => {
+            answer(query, results, json, first, copy, run_suggestions)?;
+            // Only once answered, so a slow refresh never holds up the search.
+            cache::refresh_if_stale(client);
+            Ok(())
+        }
+        Err(e) if cache::is_unavailable(&e) => {
+            eprintln!("Server unavailable, searching the cached registry: {:#}", e);
+            answer(
+                query,
+                offline_search(query)?,
+                json,
+                first,
+                copy,
+                run_suggestions,
+            )
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Shows the results of a search and hands out the command picked from them.
+fn answer(
+    query: &str,
+    results: Vec<SearchResult>,
+    json: bool,
+    first: bool,
+    copy: bool,
+    run_suggestions: bool,
+) -> Result<()> {
+    let interactive = std::io::stderr().is_terminal();
+    let results = private::merge(query, results)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+
+    if results.is_empty() {
+        eprintln!("No commands found for {:?}", query);
+        return Ok(());
+    }
+
+    let selected = if first || !interactive {
+        Some(results[0].command.clone())
+    } else {
+        prompt::select(&results)?
+    };
+    let Some(selected) = selected else {
+        return Ok(());
+    };
+
+    let command = if interactive {
+        prompt::fill(&selected, run_suggestions)?
+    } else {
+        selected.command
+    };
+
+    if copy {
+        copy_to_clipboard(&command)?;
+        eprintln!("Copied to clipboard: {}", command);
+    } else {
+        println!("{}", command);
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let client = Client::new(&cli.server, cli.token.as_deref())?;
+
+    match cli.command {
+        Commands::Search {
+            query,
+            json,
+            first,
+            copy,
+            offline,
+            run_suggestions,
+        } => search(
+            &client,
+            &query.join(" "),
+            json,
+            first,
+            copy,
+            offline,
+            run_suggestions,
+        ),
+        Commands::Sync => sync(&client),
+        Commands::Export { output } => export(&client, output),
+        Commands::Import { file, overwrite } => import(&client, file, overwrite),
+        Commands::History {
+            shell,
+            file,
+            limit,
+            no_annotate,
+        } => history(shell, file, limit, no_annotate),
+        Commands::Tldr {
+            path,
+            platform,
+            output,
+            ingest,
+        } => tldr(&client, path, platform, output, ingest),
+        Commands::Navi { action } => match action {
+            NaviAction::Import {
+                files,
+                output,
+                ingest,
+            } => navi_import(&client, files, output, ingest),
+            NaviAction::Export { output, offline } => navi_export(&client, output, offline),
+        },
+        Commands::Init { shell } => {
+            print!("{}", shell.script());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use regex::Regex;
+use spellbook_command::{Command, Placeholder, SubCommand};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+fn variable() -> &'static Regex {
+    static VARIABLE: OnceLock<Regex> = OnceLock::new();
+    VARIABLE.get_or_init(|| Regex::new(r"<(\w[\w-]*)>").unwrap())
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{(\w[\w-]*)\}").unwrap())
+}
+
+/// `<name>` variables become `{name}` placeholders.
+fn to_placeholders(text: &str) -> String {
+    variable().replace_all(text, "{$1}").to_string()
+}
+
+/// `{name}` placeholders become `<name>` variables.
+fn to_variables(text: &str) -> String {
+    placeholder().replace_all(text, "<$1>").to_string()
+}
+
+/// A `% tool, tags` section of a cheat file with its `$ name: command` suggestions.
+struct Section {
+    tags: Vec<String>,
+    snippets: Vec<(String, String)>,
+    suggestions: BTreeMap<String, String>,
+}
+
+impl Section {
+    fn into_command(self) -> Option<Command> {
+        let name = self.tags.first()?.clone();
+        let tags = self.tags[1..].to_vec();
+
+        let commands: Vec<SubCommand> = self
+            .snippets
+            .into_iter()
+            // Braces navi leaves alone would be read as spellbook placeholders.
+            .filter(|(_, snippet)| !placeholder().is_match(snippet))
+            .map(|(description, snippet)| {
+                let names: Vec<String> = variable()
+                    .captures_iter(&snippet)
+                    .map(|captures| captures[1].to_string())
+                    .fold(Vec::new(), |mut names, name| {
+                        if !names.contains(&name) {
+                            names.push(name);
+                        }
+                        names
+                    });
+                let placeholders: Vec<Placeholder> = names
+                    .into_iter()
+                    .map(|name| Placeholder {
+                        description: name.replace(['_', '-'], " "),
+                        kind: None,
+                        default: None,
+                        choices: None,
+                        pattern: None,
+                        optional: None,
+                        suggest: self
+                            .suggestions
+                            .get(&name)
+                            .map(|suggest| to_placeholders(suggest)),
+                        name,
+                    })
+                    .collect();
+
+                SubCommand {
+                    command: to_placeholders(&snippet),
+                    description: if description.is_empty() {
+                        snippet.lines().next().unwrap_or_default().to_string()
+                    } else {
+                        description
+                    },
+                    placeholders: (!placeholders.is_empty()).then_some(placeholders),
+                    tool: None,
+                    tags: (!tags.is_empty()).then(|| tags.clone()),
+                    aliases: None,
+                    questions: None,
+                }
+            })
+            .collect();
+
+        if commands.is_empty() {
+            return None;
+        }
+
+        Some(Command { name, commands })
+    }
+}
+
+/// Reads a navi `.cheat` file, each `%` section becomes a registry file named after its first
+/// tag. Snippets span consecutive lines, `;` comments and `@` extensions are ignored.
+pub fn parse(cheat: &str) -> Vec<Command> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut description = String::new();
+    let mut snippet: Vec<&str> = Vec::new();
+
+    let finish =
+        |sections: &mut Vec<Section>, description: &mut String, snippet: &mut Vec<&str>| {
+            if snippet.is_empty() {
+                return;
+            }
+            if let Some(section) = sections.last_mut() {
+                section
+                    .snippets
+                    .push((std::mem::take(description), snippet.join("\n")));
+            }
+            snippet.clear();
+        };
+
+    for line in cheat.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            finish(&mut sections, &mut description, &mut snippet);
+        } else if let Some(tags) = trimmed.strip_prefix('%') {
+            finish(&mut sections, &mut description, &mut snippet);
+            sections.push(Section {
+                tags: tags
+                    .split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                snippets: Vec::new(),
+                suggestions: BTreeMap::new(),
+            });
+        } else if let Some(text) = trimmed.strip_prefix('#') {
+            finish(&mut sections, &mut description, &mut snippet);
+            description = text.trim().to_string();
+        } else if let Some(suggestion) = trimmed.strip_prefix('$') {
+            finish(&mut sections, &mut description, &mut snippet);
+            if let (Some(section), Some((name, suggest))) =
+                (sections.last_mut(), suggestion.split_once(':'))
+            {
+                // fzf options after `---` have no spellbook equivalent.
+                let suggest = suggest.split(" --- ").next().unwrap_or_default();
+                section
+                    .suggestions
+                    .insert(name.trim().to_string(), suggest.trim().to_string());
+            }
+        } else if trimmed.starts_with(';') || trimmed.starts_with('@') {
+            continue;
+        } else {
+            snippet.push(line.trim_end());
+        }
+    }
+    finish(&mut sections, &mut description, &mut snippet);
+
+    sections
+        .into_iter()
+        .filter_map(Section::into_command)
+        .collect()
+}
+
+/// Quotes a value for `sh` so that it stays a single word.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Writes commands as a navi cheat, one `%` section per tool and set of tags. Choices become
+/// `printf` suggestions that navi does not let the user stray from.
+pub fn render(commands: &[SubCommand]) -> String {
+    let mut sections: BTreeMap<Vec<String>, Vec<&SubCommand>> = BTreeMap::new();
+    for command in commands {
+        let mut tags = vec![command.tool.clone().unwrap_or("spellbook".to_string())];
+        tags.extend(command.tags.iter().flatten().cloned());
+        sections.entry(tags).or_default().push(command);
+    }
+
+    let mut cheat = String::new();
+    for (tags, commands) in sections {
+        cheat.push_str(&format!("% {}\n", tags.join(", ")));
+
+        let mut suggestions: BTreeMap<String, String> = BTreeMap::new();
+        for command in commands {
+            cheat.push_str(&format!(
+                "\n# {}\n{}\n",
+                command.description.replace('\n', " "),
+                to_variables(&command.command)
+            ));
+
+            for placeholder in command.placeholders.iter().flatten() {
+                let suggestion = if let Some(choices) = &placeholder.choices {
+                    let choices: Vec<String> =
+                        choices.iter().map(|choice| shell_quote(choice)).collect();
+                    format!("printf '%s\\n' {} --- --prevent-extra", choices.join(" "))
+                } else if let Some(suggest) = &placeholder.suggest {
+                    to_variables(suggest)
+                } else {
+                    continue;
+                };
+                suggestions.insert(placeholder.name.clone(), suggestion);
+            }
+        }
+
+        if !suggestions.is_empty() {
+            cheat.push('\n');
+        }
+        for (name, suggestion) in suggestions {
+            cheat.push_str(&format!("$ {}: {}\n", name, suggestion));
+        }
+        cheat.push('\n');
+    }
+
+    cheat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static GIT: &str = "% git, code, vcs
+
+# Change branch
+git checkout <branch>
+
+# Push a branch to a remote
+git push <remote> <branch>
+
+; Comments and extensions are ignored
+@ other-cheat
+
+# Show a file at a revision
+git show <rev>:<file>
+
+$ branch: git branch --format='%(refname:short)' --- --preview 'git log {1}'
+$ remote: git remote
+
+% docker
+
+# Run a shell in a container
+docker exec -it <container> \\
+  sh
+
+% awk
+
+# Braces navi leaves alone cannot be told from placeholders
+awk '{print}' <file>
+";
+
+    #[test]
+    fn parses_sections_snippets_and_suggestions() {
+        let commands = parse(GIT);
+        assert_eq!(commands.len(), 2);
+
+        let git = &commands[0];
+        assert_eq!(git.name, "git");
+        assert_eq!(git.commands.len(), 3);
+        let push = &git.commands[1];
+        assert_eq!(push.command, "git push {remote} {branch}");
+        assert_eq!(push.description, "Push a branch to a remote");
+        assert_eq!(push.tags, Some(vec!["code".to_string(), "vcs".to_string()]));
+        assert_eq!(
+            push.placeholder("branch").unwrap().suggest.as_deref(),
+            Some("git branch --format='%(refname:short)'")
+        );
+        assert_eq!(
+            push.placeholder("remote").unwrap().suggest.as_deref(),
+            Some("git remote")
+        );
+        assert_eq!(git.commands[2].command, "git show {rev}:{file}");
+
+        let docker = &commands[1];
+        assert_eq!(docker.name, "docker");
+        assert_eq!(docker.commands.len(), 1);
+        assert_eq!(
+            docker.commands[0].command,
+            "docker exec -it {container} \\\n  sh"
+        );
+        assert_eq!(docker.commands[0].tags, None);
+    }
+
+    #[test]
+    fn renders_choices_as_printf_suggestions() {
+        let command = SubCommand {
+            command: "git push {remote} {branch}".to_string(),
+            description: "Push a branch".to_string(),
+            placeholders: Some(vec![
+                Placeholder {
+                    name: "remote".to_string(),
+                    description: "remote".to_string(),
+                    kind: None,
+                    default: None,
+                    choices: Some(vec!["origin".to_string(), "it's".to_string()]),
+                    pattern: None,
+                    optional: None,
+                    suggest: None,
+                },
+                Placeholder {
+                    name: "branch".to_string(),
+                    description: "branch".to_string(),
+                    kind: None,
+                    default: None,
+                    choices: None,
+                    pattern: None,
+                    optional: None,
+                    suggest: Some("git branch --list {remote}".to_string()),
+                },
+            ]),
+            tool: Some("git".to_string()),
+            tags: None,
+            aliases: None,
+            questions: None,
+        };
+
+        let cheat = render(&[command]);
+        assert_eq!(
+            cheat,
+            "% git\n\n# Push a branch\ngit push <remote> <branch>\n\n\
+             $ branch: git branch --list <remote>\n\
+             $ remote: printf '%s\\n' 'origin' 'it'\\''s' --- --prevent-extra\n\n"
+        );
+        assert_eq!(
+            parse(&cheat)[0].commands[0].command,
+            "git push {remote} {branch}"
+        );
+    }
+
+    #[test]
+    fn quotes_values_for_the_shell() {
+        assert_eq!(shell_quote("main"), "'main'");
+        assert_eq!(shell_quote("$(rm -rf ~)"), "'$(rm -rf ~)'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,133 @@
+use crate::cache;
+use crate::client::SearchResult;
+use anyhow::{anyhow, Result};
+use spellbook_command::{Command, SubCommand};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Private results shown ahead of the registry's when searching.
+static PRIVATE_LIMIT: usize = 3;
+
+/// Registry files that only live on this machine, one per tool.
+fn private_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or(anyhow!("Could not find a data directory"))?
+        .join("spellbook")
+        .join("private");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn file_name(tool: &str) -> String {
+    let name: String = tool
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.json", name)
+}
+
+/// Every private command, tagged with its tool. Files that cannot be read are reported and
+/// skipped so that one broken file does not break every search.
+pub fn load() -> Result<Vec<SubCommand>> {
+    let mut commands = Vec::new();
+    for entry in fs::read_dir(private_dir()?)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            match fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|body| Ok(serde_json::from_slice::<Command>(&body)?))
+            {
+                Ok(command) => commands.extend(command.sub_commands()),
+                Err(e) => eprintln!(
+                    "Skipping invalid private command file {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    }
+    Ok(commands)
+}
+
+/// Adds commands to the private collection, returning how many were not already in it.
+pub fn save(commands: Vec<SubCommand>) -> Result<usize> {
+    let existing: HashSet<String> = load()?.into_iter().map(|command| command.command).collect();
+    let mut saved = 0;
+
+    for command in commands {
+        if existing.contains(&command.command) {
+            continue;
+        }
+        let tool = command.tool.clone().unwrap_or("shell".to_string());
+        let path = private_dir()?.join(file_name(&tool));
+        let mut file = match fs::read(&path) {
+            Ok(body) => serde_json::from_slice(&body)?,
+            Err(_) => Command {
+                name: tool,
+                commands: Vec::new(),
+            },
+        };
+
+        file.commands.push(SubCommand {
+            tool: None,
+            ..command
+        });
+        fs::write(&path, serde_json::to_string_pretty(&file)?)?;
+        saved += 1;
+    }
+
+    Ok(saved)
+}
+
+/// Private commands matching at least half of the query, placed ahead of the registry's results.
+pub fn merge(query: &str, results: Vec<SearchResult>) -> Result<Vec<SearchResult>> {
+    let commands = load()?;
+    if commands.is_empty() {
+        return Ok(results);
+    }
+
+    let query_tokens: HashSet<String> = cache::tokens(query).into_iter().collect();
+    let snapshot = cache::Snapshot {
+        version: 0,
+        commands: commands
+            .into_iter()
+            .filter(|command| {
+                let document = cache::document(command);
+                let matched = query_tokens
+                    .iter()
+                    .filter(|token| document.contains(*token))
+                    .count();
+                matched * 2 >= query_tokens.len().max(1)
+            })
+            .collect(),
+    };
+
+    let mut merged: Vec<SearchResult> = cache::search(&snapshot, query, PRIVATE_LIMIT)
+        .into_iter()
+        .map(|result| SearchResult {
+            stage: Some("private".to_string()),
+            ..result
+        })
+        .collect();
+    let private: HashSet<String> = merged
+        .iter()
+        .map(|result| result.command.command.clone())
+        .collect();
+    merged.extend(
+        results
+            .into_iter()
+            .filter(|result| !private.contains(&result.command.command)),
+    );
+
+    Ok(merged)
+}
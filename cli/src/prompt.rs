@@ -0,0 +1,197 @@
+use crate::client::SearchResult;
+use crate::history::Candidate;
+use crate::navi::shell_quote;
+use anyhow::Result;
+use dialoguer::console::Term;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, Select};
+use spellbook_command::{Placeholder, SubCommand};
+use std::collections::HashMap;
+use std::process::Stdio;
+
+// Prompts go to stderr so that stdout only ever carries the chosen command.
+
+pub fn query() -> Result<String> {
+    let query = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Search spellbook")
+        .interact_text_on(&Term::stderr())?;
+
+    Ok(query)
+}
+
+pub fn select(results: &[SearchResult]) -> Result<Option<SubCommand>> {
+    let items: Vec<String> = results
+        .iter()
+        .map(|result| {
+            format!(
+                "{}  ({})",
+                result.command.command, result.command.description
+            )
+        })
+        .collect();
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select a command")
+        .items(&items)
+        .default(0)
+        .interact_on_opt(&Term::stderr())?;
+
+    Ok(selection.map(|i| results[i].command.clone()))
+}
+
+/// Asks what a command from the shell history does, `None` to leave it out.
+pub fn describe(candidate: &Candidate) -> Result<Option<String>> {
+    eprintln!(
+        "\n{}\n  run {} times, last as: {}",
+        candidate.command.command, candidate.count, candidate.example
+    );
+    let description = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Description (empty to skip)")
+        .allow_empty(true)
+        .interact_text_on(&Term::stderr())?;
+
+    Ok(Some(description.trim().to_string()).filter(|description| !description.is_empty()))
+}
+
+/// Asks whether the values of the candidate's last run should become its placeholders'
+/// defaults, they may be anything from a branch name to a password.
+pub fn keep_values(candidate: &Candidate) -> Result<bool> {
+    if candidate.values.is_empty() {
+        return Ok(false);
+    }
+
+    let mut values: Vec<String> = candidate
+        .values
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    values.sort();
+    let keep = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Keep {} as defaults?", values.join(" ")))
+        .default(false)
+        .interact_on(&Term::stderr())?;
+
+    Ok(keep)
+}
+
+/// Lines printed by a placeholder's `suggest` command, nothing if it fails or the user declines
+/// to run it. Registry commands come from whoever can write to the registry, so the command is
+/// always shown before it runs.
+fn suggestions(suggest: &str, values: &HashMap<String, String>) -> Result<Vec<String>> {
+    let quoted = values
+        .iter()
+        .map(|(name, value)| (name.clone(), shell_quote(value)))
+        .collect();
+    let script = spellbook_command::substitute(suggest, &quoted);
+
+    let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Run `{}` for suggestions?", script))
+        .default(false)
+        .interact_on(&Term::stderr())?;
+    if !confirmed {
+        return Ok(Vec::new());
+    }
+
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .stderr(Stdio::null())
+        .output();
+
+    Ok(match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect(),
+        _ => Vec::new(),
+    })
+}
+
+fn input(
+    theme: &ColorfulTheme,
+    prompt: String,
+    placeholder: Option<Placeholder>,
+) -> Result<String> {
+    let is_optional = placeholder
+        .as_ref()
+        .is_some_and(|placeholder| placeholder.is_optional());
+    let mut input = Input::<String>::with_theme(theme)
+        .with_prompt(prompt)
+        .allow_empty(is_optional);
+    if let Some(default) = placeholder
+        .as_ref()
+        .and_then(|placeholder| placeholder.default.clone())
+    {
+        input = input.default(default);
+    }
+    if let Some(placeholder) = placeholder {
+        input = input.validate_with(move |value: &String| -> Result<(), String> {
+            if value.is_empty() && placeholder.is_optional() {
+                return Ok(());
+            }
+            placeholder.check(value)
+        });
+    }
+
+    Ok(input.interact_text_on(&Term::stderr())?)
+}
+
+/// Asks for a value for every placeholder of the command, offering its choices, default or, with
+/// `run_suggestions`, the output of its `suggest` command.
+pub fn fill(command: &SubCommand, run_suggestions: bool) -> Result<String> {
+    let theme = ColorfulTheme::default();
+    let mut values = HashMap::new();
+
+    for name in command.placeholder_names() {
+        let placeholder = command.placeholder(&name).cloned();
+        let prompt = match &placeholder {
+            Some(placeholder) => format!("{} ({})", name, placeholder.description),
+            None => name.clone(),
+        };
+
+        let choices = placeholder
+            .as_ref()
+            .and_then(|placeholder| placeholder.choices.clone());
+        let suggest = placeholder
+            .as_ref()
+            .and_then(|placeholder| placeholder.suggest.as_ref())
+            .filter(|_| run_suggestions);
+        let suggested = match suggest {
+            Some(suggest) => suggestions(suggest, &values)?,
+            None => Vec::new(),
+        };
+
+        let value = if let Some(choices) = choices {
+            let default = placeholder
+                .as_ref()
+                .and_then(|placeholder| placeholder.default.as_ref())
+                .and_then(|default| choices.iter().position(|choice| choice == default))
+                .unwrap_or(0);
+            let i = Select::with_theme(&theme)
+                .with_prompt(prompt)
+                .items(&choices)
+                .default(default)
+                .interact_on(&Term::stderr())?;
+            choices[i].clone()
+        } else if !suggested.is_empty() {
+            let i = Select::with_theme(&theme)
+                .with_prompt(prompt.clone())
+                .items(&suggested)
+                .item("Enter another value")
+                .default(0)
+                .interact_on(&Term::stderr())?;
+            match suggested.get(i) {
+                Some(value) => value.clone(),
+                None => input(&theme, prompt, placeholder)?,
+            }
+        } else {
+            input(&theme, prompt, placeholder)?
+        };
+
+        values.insert(name, value);
+    }
+
+    Ok(command.substitute(&values).trim().to_string())
+}
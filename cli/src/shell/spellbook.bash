@@ -0,0 +1,14 @@
+# spellbook shell integration for bash, add to ~/.bashrc:
+#   eval "$(spellbook init bash)"
+# Press Ctrl-G (or $SPELLBOOK_KEY) to search spellbook for the current line.
+
+__spellbook_widget() {
+  local selected
+  selected="$(spellbook search -- "$READLINE_LINE")" || return
+  if [ -n "$selected" ]; then
+    READLINE_LINE="$selected"
+    READLINE_POINT=${#READLINE_LINE}
+  fi
+}
+
+bind -x "\"${SPELLBOOK_KEY:-\C-g}\": __spellbook_widget"
@@ -0,0 +1,18 @@
+# spellbook shell integration for fish, add to ~/.config/fish/config.fish:
+#   spellbook init fish | source
+# Press Ctrl-G (or $SPELLBOOK_KEY) to search spellbook for the current line.
+
+function __spellbook_widget
+    set -l selected (spellbook search -- (commandline) </dev/tty)
+    if test $status -eq 0 -a -n "$selected"
+        commandline -r -- $selected
+    end
+    commandline -f repaint
+end
+
+set -l __spellbook_key \cg
+set -q SPELLBOOK_KEY; and set __spellbook_key $SPELLBOOK_KEY
+bind $__spellbook_key __spellbook_widget
+if bind -M insert >/dev/null 2>&1
+    bind -M insert $__spellbook_key __spellbook_widget
+end
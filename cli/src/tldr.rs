@@ -0,0 +1,321 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use spellbook_command::{Command, Placeholder, PlaceholderType, SubCommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The spellbook tag for a tldr-pages platform directory.
+fn platform_tag(platform: &str) -> String {
+    match platform {
+        "common" => "cross-platform".to_string(),
+        "osx" => "macos".to_string(),
+        platform => platform.to_string(),
+    }
+}
+
+/// The `pages` directory of a tldr-pages checkout, or the path itself if it already is one.
+fn pages_dir(path: &Path) -> Result<PathBuf> {
+    let pages = path.join("pages");
+    if pages.is_dir() {
+        return Ok(pages);
+    }
+    if path.file_name().is_some_and(|name| name == "pages") && path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    Err(anyhow!(
+        "{} is not a tldr-pages checkout, it has no pages directory",
+        path.display()
+    ))
+}
+
+/// Platform directories to import, all of them when none are asked for.
+pub fn platforms(path: &Path, only: &[String]) -> Result<Vec<String>> {
+    let pages = pages_dir(path)?;
+    let mut platforms = Vec::new();
+    for entry in fs::read_dir(&pages)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() && (only.is_empty() || only.contains(&name)) {
+            platforms.push(name);
+        }
+    }
+
+    if let Some(unknown) = only.iter().find(|platform| !platforms.contains(platform)) {
+        return Err(anyhow!("No tldr pages for platform {}", unknown));
+    }
+
+    platforms.sort();
+    Ok(platforms)
+}
+
+/// Converts every page of a platform, skipping pages without usable examples.
+pub fn convert_platform(path: &Path, platform: &str) -> Result<Vec<Command>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(pages_dir(path)?.join(platform))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.extension().is_some_and(|extension| extension == "md"))
+        .collect();
+    files.sort();
+
+    let mut commands = Vec::new();
+    for file in files {
+        let page = fs::read_to_string(&file)?;
+        let name = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Some(command) = convert_page(&name, &page, platform) {
+            commands.push(command);
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Converts a tldr page, each `- description:` followed by a `` `command` `` line becomes a
+/// sub command.
+pub fn convert_page(name: &str, page: &str, platform: &str) -> Option<Command> {
+    let title = page
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string())
+        .unwrap_or(name.to_string());
+
+    let mut commands = Vec::new();
+    let mut description: Option<String> = None;
+    for line in page.lines().map(str::trim) {
+        if let Some(text) = line.strip_prefix("- ") {
+            description = Some(clean_description(text));
+        } else if let Some(example) = line
+            .strip_prefix('`')
+            .and_then(|line| line.strip_suffix('`'))
+        {
+            if let Some(description) = description.take() {
+                commands.extend(convert_example(example, description, platform));
+            }
+        }
+    }
+
+    if commands.is_empty() {
+        return None;
+    }
+
+    Some(Command {
+        name: title,
+        commands,
+    })
+}
+
+/// `[c]reate an archive:` becomes `Create an archive`, tldr marks option mnemonics this way.
+fn clean_description(text: &str) -> String {
+    static MNEMONIC: OnceLock<Regex> = OnceLock::new();
+    let mnemonic = MNEMONIC.get_or_init(|| Regex::new(r"\[(\w+)\]").unwrap());
+    let text = mnemonic.replace_all(text.trim().trim_end_matches(':'), "$1");
+
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn convert_example(example: &str, description: String, platform: &str) -> Option<SubCommand> {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    static LITERAL_PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let token = TOKEN.get_or_init(|| Regex::new(r"\{\{(.*?)\}\}").unwrap());
+    let literal_placeholder =
+        LITERAL_PLACEHOLDER.get_or_init(|| Regex::new(r"\{[\w-]+\}").unwrap());
+
+    // Single braces outside of tldr's tokens would be read as spellbook placeholders.
+    if literal_placeholder.is_match(&token.replace_all(example, "")) {
+        return None;
+    }
+
+    let mut placeholders: Vec<Placeholder> = Vec::new();
+    let mut command = String::new();
+    let mut last = 0;
+    for captures in token.captures_iter(example) {
+        let whole = captures.get(0).unwrap();
+        let text = &captures[1];
+        command.push_str(&example[last..whole.start()]);
+        last = whole.end();
+
+        if let Some(option) = option_alternative(text) {
+            command.push_str(option);
+            continue;
+        }
+
+        let name = match placeholders.iter().find(|p| p.description == text) {
+            Some(existing) => existing.name.clone(),
+            None => {
+                let placeholder = placeholder(text, &placeholders);
+                let name = placeholder.name.clone();
+                placeholders.push(placeholder);
+                name
+            }
+        };
+        command.push_str(&format!("{{{}}}", name));
+    }
+    command.push_str(&example[last..]);
+
+    Some(SubCommand {
+        command,
+        description,
+        placeholders: (!placeholders.is_empty()).then_some(placeholders),
+        tool: None,
+        tags: Some(vec![platform_tag(platform)]),
+        aliases: None,
+        questions: None,
+    })
+}
+
+/// `{{[-f|--force]}}` marks the short and long form of an option, the long form is kept as the
+/// more readable of the two.
+fn option_alternative(text: &str) -> Option<&str> {
+    let alternatives = text.strip_prefix('[')?.strip_suffix(']')?;
+    alternatives
+        .rsplit('|')
+        .next()
+        .filter(|option| option.starts_with('-'))
+}
+
+/// A placeholder named after the tldr example value, `{{path/to/file1 path/to/file2 ...}}`
+/// becomes `file`, with a number appended if the name is taken.
+fn placeholder(text: &str, existing: &[Placeholder]) -> Placeholder {
+    let first = text.split_whitespace().next().unwrap_or_default();
+    let last_segment = first
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let stem = last_segment
+        .split('.')
+        .find(|part| !part.is_empty())
+        .unwrap_or_default();
+
+    let mut base = String::new();
+    for c in stem.to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            base.push(c);
+        } else if !base.ends_with('_') {
+            base.push('_');
+        }
+    }
+    let base = base
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .trim_matches('_');
+    let base = match base {
+        "" => "value",
+        base => base,
+    };
+
+    let mut name = base.to_string();
+    let mut n = 2;
+    while existing.iter().any(|placeholder| placeholder.name == name) {
+        name = format!("{}_{}", base, n);
+        n += 1;
+    }
+
+    // Example numbers such as ports make sensible defaults, other examples are only hints.
+    let (kind, default) = if text.contains("path/to") {
+        (Some(PlaceholderType::Path), None)
+    } else if text.parse::<i64>().is_ok() {
+        (Some(PlaceholderType::Integer), Some(text.to_string()))
+    } else {
+        (None, None)
+    };
+
+    Placeholder {
+        name,
+        description: text.to_string(),
+        kind,
+        default,
+        choices: None,
+        pattern: None,
+        optional: None,
+        suggest: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TAR: &str = "# tar
+
+> Archiving utility.
+> Often combined with a compression method, such as `gzip` or `bzip2`.
+> More information: <https://www.gnu.org/software/tar>.
+
+- [c]reate an archive and write it to a [f]ile:
+
+`tar {{[-c|--create]}} {{[-f|--file]}} {{path/to/target.tar}} {{path/to/file1 path/to/file2 ...}}`
+
+- E[x]tract a (compressed) archive [f]ile into the current directory [v]erbosely:
+
+`tar {{[-x|--extract]}} {{[-v|--verbose]}} {{[-f|--file]}} {{path/to/source.tar[.gz|.bz2|.xz]}}`
+
+- Extract files matching a pattern from an archive:
+
+`tar {{[-x|--extract]}} {{[-f|--file]}} {{path/to/source.tar}} --wildcards \"{{*.html}}\"`
+";
+
+    #[test]
+    fn converts_a_page() {
+        let command = convert_page("tar", TAR, "common").unwrap();
+        assert_eq!(command.name, "tar");
+        assert_eq!(command.commands.len(), 3);
+
+        let create = &command.commands[0];
+        assert_eq!(
+            create.description,
+            "Create an archive and write it to a file"
+        );
+        assert_eq!(create.command, "tar --create --file {target} {file}");
+        assert_eq!(create.tags, Some(vec!["cross-platform".to_string()]));
+        let target = create.placeholder("target").unwrap();
+        assert_eq!(target.description, "path/to/target.tar");
+        assert_eq!(target.kind, Some(PlaceholderType::Path));
+
+        let extract = &command.commands[1];
+        assert_eq!(
+            extract.description,
+            "Extract a (compressed) archive file into the current directory verbosely"
+        );
+        assert_eq!(extract.command, "tar --extract --verbose --file {source}");
+
+        let pattern = &command.commands[2];
+        assert_eq!(
+            pattern.command,
+            "tar --extract --file {source} --wildcards \"{value}\""
+        );
+    }
+
+    #[test]
+    fn numbers_repeated_names_and_keeps_numeric_defaults() {
+        let page = "# ssh\n\n- Connect on a port:\n\n`ssh -p {{2222}} {{username}}@{{remote_host}} {{path/to/a}} {{path/to/b}}`\n";
+        let command = convert_page("ssh", page, "linux").unwrap();
+        let sub_command = &command.commands[0];
+        assert_eq!(
+            sub_command.command,
+            "ssh -p {value} {username}@{remote_host} {a} {b}"
+        );
+        let port = sub_command.placeholder("value").unwrap();
+        assert_eq!(port.kind, Some(PlaceholderType::Integer));
+        assert_eq!(port.default.as_deref(), Some("2222"));
+        assert_eq!(sub_command.tags, Some(vec!["linux".to_string()]));
+    }
+
+    #[test]
+    fn skips_examples_with_literal_braces() {
+        let page = "# awk\n\n- Print every line:\n\n`awk '{print}' {{path/to/file}}`\n";
+        assert!(convert_page("awk", page, "common").is_none());
+    }
+
+    #[test]
+    fn maps_platform_tags() {
+        assert_eq!(platform_tag("common"), "cross-platform");
+        assert_eq!(platform_tag("osx"), "macos");
+        assert_eq!(platform_tag("linux"), "linux");
+    }
+}
@@ -0,0 +1,169 @@
+//! Registry files and their commands, shared by the server, the cli and the web app.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Command {
+    pub name: String,
+    pub commands: Vec<SubCommand>,
+}
+
+impl Command {
+    /// The sub commands of this file, each tagged with the name of the tool they belong to.
+    pub fn sub_commands(self) -> Vec<SubCommand> {
+        let name = self.name;
+        self.commands
+            .into_iter()
+            .map(|sub_command| SubCommand {
+                tool: Some(name.clone()),
+                ..sub_command
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Eq, Hash)]
+pub struct SubCommand {
+    pub command: String,
+    pub description: String,
+    pub placeholders: Option<Vec<Placeholder>>,
+    pub tool: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub aliases: Option<Vec<String>>,
+    pub questions: Option<Vec<String>>,
+}
+
+impl SubCommand {
+    /// Names of the `{placeholder}`s used in the command, in order of first appearance.
+    pub fn placeholder_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (_, name) in placeholder_spans(&self.command) {
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    pub fn placeholder(&self, name: &str) -> Option<&Placeholder> {
+        self.placeholders
+            .iter()
+            .flatten()
+            .find(|placeholder| placeholder.name == name)
+    }
+
+    /// The command with every placeholder that has a value substituted, others are left as is.
+    pub fn substitute(&self, values: &HashMap<String, String>) -> String {
+        substitute(&self.command, values)
+    }
+}
+
+/// Substitutes `{name}` placeholders in any text, such as a placeholder's `suggest` command.
+pub fn substitute(text: &str, values: &HashMap<String, String>) -> String {
+    let mut substituted = String::new();
+    let mut last = 0;
+    for (start, name) in placeholder_spans(text) {
+        if let Some(value) = values.get(name) {
+            substituted.push_str(&text[last..start]);
+            substituted.push_str(value);
+            last = start + name.len() + 2;
+        }
+    }
+    substituted.push_str(&text[last..]);
+    substituted
+}
+
+/// Byte offsets and names of the `{name}` placeholders in a command.
+fn placeholder_spans(command: &str) -> Vec<(usize, &str)> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    let mut spans = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = command[offset..].find('{') {
+        let start = offset + start;
+        match command[start + 1..].find('}') {
+            Some(len) if len > 0 && command[start + 1..start + 1 + len].chars().all(is_name) => {
+                spans.push((start, &command[start + 1..start + 1 + len]));
+                offset = start + len + 2;
+            }
+            _ => offset = start + 1,
+        }
+    }
+
+    spans
+}
+
+impl fmt::Display for SubCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} : {}", self.command, self.description)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Eq, Hash)]
+pub struct Placeholder {
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "type")]
+    pub kind: Option<PlaceholderType>,
+    pub default: Option<String>,
+    pub choices: Option<Vec<String>>,
+    pub pattern: Option<String>,
+    pub optional: Option<bool>,
+    /// Shell command printing suggested values one per line, it may use earlier placeholders.
+    pub suggest: Option<String>,
+}
+
+impl Placeholder {
+    pub fn is_optional(&self) -> bool {
+        self.optional.unwrap_or(false)
+    }
+
+    /// Checks a value against the placeholder's type, choices and pattern.
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        let type_error = match self.kind {
+            Some(PlaceholderType::Integer) if value.parse::<i64>().is_err() => {
+                Some("expected an integer")
+            }
+            Some(PlaceholderType::Number) if value.parse::<f64>().is_err() => {
+                Some("expected a number")
+            }
+            Some(PlaceholderType::Boolean) if !["true", "false"].contains(&value) => {
+                Some("expected true or false")
+            }
+            Some(PlaceholderType::Path) if value.trim().is_empty() => Some("expected a path"),
+            _ => None,
+        };
+        if let Some(type_error) = type_error {
+            return Err(format!("{}, got {:?}", type_error, value));
+        }
+
+        if let Some(choices) = &self.choices {
+            if !choices.iter().any(|choice| choice == value) {
+                return Err(format!("{:?} is not one of {}", value, choices.join("|")));
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let regex = Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|e| format!("invalid pattern {:?}: {}", pattern, e))?;
+            if !regex.is_match(value) {
+                return Err(format!("{:?} does not match {}", value, pattern));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Path,
+}
@@ -9,7 +9,10 @@ use gloo_timers::callback::Timeout;
 use gloo_timers::future::TimeoutFuture;
 use std::rc::Rc;
 use yew::{html, Callback, Html};
-mod command;
+mod placeholder_form;
+mod recent;
+use placeholder_form::PlaceholderForm;
+use spellbook_command::SubCommand;
 
 #[function_component]
 fn ClipBoardIcon() -> Html {
@@ -31,6 +34,8 @@ fn App() -> Html {
     let results = (*results_handle).clone();
     let is_loading_handle = use_state(|| false);
     let is_loading = (*is_loading_handle).clone();
+    let selected_handle = use_state(|| None::<String>);
+    let selected = (*selected_handle).clone();
 
     let search: Callback<String> = {
         Callback::from({
@@ -50,7 +55,7 @@ fn App() -> Html {
                         .await
                         .unwrap();
 
-                    let res: Vec<command::SubCommand> = serde_json::from_value(res).unwrap();
+                    let res: Vec<SubCommand> = serde_json::from_value(res).unwrap();
 
                     log!(serde_wasm_bindgen::to_value(&res).unwrap());
 
@@ -172,17 +177,47 @@ fn App() -> Html {
                                     results.into_iter().map(|result| {
                                         let command = result.command.clone();
                                         let clipboard = clipboard.clone();
+                                        let has_placeholders = !result.placeholder_names().is_empty();
+                                        let is_selected = selected.as_ref() == Some(&result.command);
+                                        let toggle_form = {
+                                            let selected_handle = selected_handle.clone();
+                                            let command = command.clone();
+                                            Callback::from(move |_: MouseEvent| {
+                                                if *selected_handle == Some(command.clone()) {
+                                                    selected_handle.set(None);
+                                                } else if has_placeholders {
+                                                    selected_handle.set(Some(command.clone()));
+                                                }
+                                            })
+                                        };
+                                        let onclick_copy = if has_placeholders {
+                                            toggle_form.clone()
+                                        } else {
+                                            let clipboard = clipboard.clone();
+                                            Callback::from(move |_: MouseEvent| clipboard.emit(command.clone()))
+                                        };
                                         html! {
-                                            <div class="bg-[#252525] rounded-md text-xl w-full  rounded-md text-white flex space-y-2 p-4 items-center justify-between" >
-                                                <div class="flex flex-col space-y-2">
-                                                    <h1 class="text-lg text-white"> {result.command} </h1>
-                                                    <p class="text-gray-400 text-sm"> {result.description} </p>
+                                            <div class="bg-[#252525] rounded-md text-xl w-full  rounded-md text-white flex flex-col p-4" >
+                                                <div class="flex space-y-2 items-center justify-between">
+                                                    <div class="flex flex-col space-y-2 cursor-pointer" onclick={toggle_form}>
+                                                        <h1 class="text-lg text-white"> {result.command.clone()} </h1>
+                                                        <p class="text-gray-400 text-sm"> {result.description.clone()} </p>
+                                                    </div>
+                                                    <button
+                                                    onclick={onclick_copy}
+                                                    class="bg-gray-100/10 p-3 rounded-md hover:bg-gray-100/20">
+                                                        <ClipBoardIcon />
+                                                    </button>
                                                 </div>
-                                                <button
-                                                onclick={move |_| clipboard.emit(command.clone())}
-                                                class="bg-gray-100/10 p-3 rounded-md hover:bg-gray-100/20">
-                                                    <ClipBoardIcon />
-                                                </button>
+                                                {
+                                                    if is_selected {
+                                                        html! {
+                                                            <PlaceholderForm command={result.clone()} on_copy={clipboard.clone()} />
+                                                        }
+                                                    } else {
+                                                        html! {<> </>}
+                                                    }
+                                                }
                                             </div>
                                         }
                                     }).collect::<Html>()
@@ -0,0 +1,130 @@
+use crate::recent;
+use spellbook_command::{PlaceholderType, SubCommand};
+use std::collections::HashMap;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct PlaceholderFormProps {
+    pub command: SubCommand,
+    pub on_copy: Callback<String>,
+}
+
+/// Starts every placeholder at its most recently used value, or its default.
+fn initial_values(
+    command: &SubCommand,
+    recent_values: &HashMap<String, Vec<String>>,
+) -> HashMap<String, String> {
+    command
+        .placeholder_names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = recent_values
+                .get(&name)
+                .and_then(|values| values.first().cloned())
+                .or_else(|| {
+                    command
+                        .placeholder(&name)
+                        .and_then(|placeholder| placeholder.default.clone())
+                })?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[function_component]
+pub fn PlaceholderForm(props: &PlaceholderFormProps) -> Html {
+    let recent_values = use_state(recent::load);
+    let values_handle = use_state(|| initial_values(&props.command, &recent_values));
+    let values = (*values_handle).clone();
+
+    // Optional placeholders left empty disappear, required ones stay visible until filled.
+    let filled: HashMap<String, String> = props
+        .command
+        .placeholder_names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = values.get(&name).cloned().unwrap_or_default();
+            let is_optional = props
+                .command
+                .placeholder(&name)
+                .is_some_and(|placeholder| placeholder.is_optional());
+            (!value.is_empty() || is_optional).then_some((name, value))
+        })
+        .collect();
+    let preview = props.command.substitute(&filled).trim().to_string();
+
+    let oncopy = {
+        let on_copy = props.on_copy.clone();
+        let values = values.clone();
+        let preview = preview.clone();
+        Callback::from(move |_: MouseEvent| {
+            recent::remember(&values);
+            on_copy.emit(preview.clone());
+        })
+    };
+
+    html! {
+        <div class="flex flex-col space-y-3 pt-2">
+            {
+                props.command.placeholder_names().into_iter().map(|name| {
+                    let placeholder = props.command.placeholder(&name).cloned();
+                    let description = placeholder.as_ref().map(|placeholder| placeholder.description.clone()).unwrap_or_default();
+                    let input_type = match placeholder.as_ref().and_then(|placeholder| placeholder.kind) {
+                        Some(PlaceholderType::Integer) | Some(PlaceholderType::Number) => "number",
+                        _ => "text",
+                    };
+                    let suggestions: Vec<String> = placeholder
+                        .as_ref()
+                        .and_then(|placeholder| placeholder.choices.clone())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .chain(recent_values.get(&name).cloned().unwrap_or_default())
+                        .collect();
+                    let list_id = format!("placeholder-{}", name);
+                    let value = values.get(&name).cloned().unwrap_or_default();
+
+                    let oninput = {
+                        let values_handle = values_handle.clone();
+                        let name = name.clone();
+                        Callback::from(move |e: InputEvent| {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            let mut values = (*values_handle).clone();
+                            values.insert(name.clone(), input.value());
+                            values_handle.set(values);
+                        })
+                    };
+
+                    html! {
+                        <label class="flex flex-col space-y-1">
+                            <span class="text-sm text-gray-400">{format!("{{{}}}", name)}</span>
+                            <input
+                                class="bg-[#1A1A1A] rounded-md px-3 py-2 text-base text-white outline-none"
+                                type={input_type}
+                                placeholder={description}
+                                list={list_id.clone()}
+                                {value}
+                                {oninput}
+                            />
+                            <datalist id={list_id}>
+                                {
+                                    suggestions.into_iter().map(|suggestion| html! {
+                                        <option value={suggestion} />
+                                    }).collect::<Html>()
+                                }
+                            </datalist>
+                        </label>
+                    }
+                }).collect::<Html>()
+            }
+            <div class="flex items-center justify-between bg-[#1A1A1A] rounded-md px-3 py-2">
+                <code class="text-base text-[#FF5B04]">{preview}</code>
+                <button
+                    onclick={oncopy}
+                    class="bg-gray-100/10 px-3 py-1 rounded-md text-sm hover:bg-gray-100/20">
+                    {"Copy"}
+                </button>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+
+static STORAGE_KEY: &str = "spellbook.recent-placeholder-values";
+static MAX_RECENT_VALUES: usize = 5;
+
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// Recently used values per placeholder name, most recent first.
+pub fn load() -> HashMap<String, Vec<String>> {
+    storage()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn remember(values: &HashMap<String, String>) {
+    let Some(storage) = storage() else {
+        return;
+    };
+
+    let mut recent = load();
+    for (name, value) in values.iter().filter(|(_, value)| !value.is_empty()) {
+        let recent_values = recent.entry(name.clone()).or_default();
+        recent_values.retain(|recent_value| recent_value != value);
+        recent_values.insert(0, value.clone());
+        recent_values.truncate(MAX_RECENT_VALUES);
+    }
+
+    if let Ok(json) = serde_json::to_string(&recent) {
+        let _ = storage.set_item(STORAGE_KEY, &json);
+    }
+}